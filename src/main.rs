@@ -1,22 +1,37 @@
+use avian3d::collision::collider::contact_query;
+use avian3d::diagnostics::{PhysicsTotalDiagnostics, PhysicsTotalDiagnosticsPlugin};
 use avian3d::math::AdjustPrecision;
 use avian3d::prelude::*;
+use bevy::animation::AnimationTargetId;
+use bevy::app::AnimationSystems;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext, LoadState};
+use bevy::audio::Volume;
 use bevy::color::palettes::css;
+use bevy::ecs::system::{EntityCommands, SystemParam};
+use bevy::gltf::GltfNode;
 use bevy::input::mouse::{MouseMotion, MouseWheel};
-use bevy::platform::collections::HashMap;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
-use bevy::render::RenderPlugin;
-use bevy::render::settings::{Backends, RenderCreation, WgpuSettings};
 use bevy::scene::SceneInstanceReady;
+use bevy::time::Stopwatch;
+use bevy_tnua::basis_capabilities::{
+    TnuaBasisWithFloating, TnuaBasisWithGround, TnuaBasisWithHeadroom, TnuaBasisWithSpring,
+};
 use bevy_tnua::builtins::*;
 use bevy_tnua::math::AsF32;
 use bevy_tnua::{
-    TnuaAnimatingState, TnuaAnimatingStateDirective, TnuaConfigModifier,
-    builtins::TnuaBuiltinJumpMemory, prelude::*,
+    TnuaAction, TnuaActionContext, TnuaActionInitiationDirective, TnuaActionLifecycleDirective,
+    TnuaActionLifecycleStatus, TnuaAnimatingState, TnuaAnimatingStateDirective, TnuaBasisContext,
+    TnuaConfigModifier, TnuaMotor, TnuaProximitySensor, builtins::TnuaBuiltinJumpMemory,
+    prelude::*,
 };
 use bevy_tnua_avian3d::prelude::*;
-use std::f32::consts::{FRAC_PI_2, PI};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::ops::Range;
+use std::time::Duration;
 
-const GLTF_PATH: &str = "models/characters/Knight.glb";
 const ANIMATIONS: [&str; 76] = [
     "1H_Melee_Attack_Chop",
     "1H_Melee_Attack_Slice_Diagonal",
@@ -107,21 +122,177 @@ fn main() {
                 }),
                 ..default()
             }),
-            PhysicsPlugins::default(),
-            PhysicsDebugPlugin::default(),
+            PhysicsPlugins::default().with_collision_hooks::<ConveyorHooks>(),
+            PhysicsDebugPlugin,
+            PhysicsTotalDiagnosticsPlugin,
             TnuaControllerPlugin::<ControlScheme>::new(FixedUpdate),
             TnuaAvian3dPlugin::new(FixedUpdate),
         ))
-        .add_systems(Startup, (setup_level, setup_player, setup_domino_scene))
+        .add_systems(Startup, (setup_level, setup_debug_panel))
+        .init_state::<GameState>()
+        .add_computed_state::<InCourse>()
+        .add_systems(OnEnter(GameState::MainMenu), setup_main_menu)
+        .add_systems(OnEnter(GameState::Loading), setup_loading_screen)
+        .add_systems(
+            OnEnter(GameState::LoadingFailed),
+            setup_loading_error_screen,
+        )
+        .add_systems(OnEnter(GameState::Paused), setup_pause_menu)
+        .add_systems(OnExit(GameState::Paused), resume_time)
+        .add_systems(OnEnter(GameState::Results), show_completion_screen)
+        .add_systems(
+            OnEnter(InCourse),
+            (
+                spawn_selected_course,
+                setup_build_phase.run_if(resource_exists::<PuzzleMode>),
+            )
+                .chain(),
+        )
+        .add_systems(OnExit(InCourse), clear_course)
+        .insert_resource(Settings::load())
+        .init_asset_loader::<ControlSchemeConfigLoader>()
+        .init_resource::<ChainRecording>()
+        .init_resource::<ThrownBallAssets>()
+        .init_resource::<DominoAssets>()
+        .init_resource::<KnockdownsEnabled>()
+        .init_resource::<CourseTimer>()
+        .init_resource::<CourseProgress>()
+        .add_message::<HeavyImpact>()
+        .add_message::<TriggerFired>()
+        .add_systems(
+            FixedUpdate,
+            (sense_walls, apply_controls, detect_hits)
+                .chain()
+                .in_set(TnuaUserControlsSystems),
+        )
         .add_systems(
             FixedUpdate,
-            (apply_controls).in_set(TnuaUserControlsSystems),
+            (
+                (
+                    apply_melee_strikes,
+                    sync_crouch_collider.run_if(resource_exists::<SelectedCharacter>),
+                )
+                    .after(TnuaPipelineSystems::Logic),
+                record_chain,
+                move_platforms,
+                carry_on_conveyors
+                    .after(TnuaPipelineSystems::Sensors)
+                    .before(TnuaPipelineSystems::Logic),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                (
+                    choose_character.run_if(
+                        resource_exists::<SelectedCourse>
+                            .and(not(resource_exists::<SelectedCharacter>)),
+                    ),
+                    choose_course.run_if(not(resource_exists::<SelectedCourse>)),
+                )
+                    .chain()
+                    .run_if(
+                        in_state(GameState::MainMenu).and(not(resource_exists::<SettingsMenu>)),
+                    ),
+                (
+                    open_settings.run_if(
+                        in_state(GameState::MainMenu)
+                            .or(in_state(GameState::Paused))
+                            .and(not(resource_exists::<SettingsMenu>)),
+                    ),
+                    (handle_settings_menu, update_settings_menu)
+                        .chain()
+                        .run_if(resource_exists::<SettingsMenu>),
+                )
+                    .chain()
+                    .after(handle_pause_menu),
+                apply_settings.run_if(resource_changed::<Settings>),
+                track_loading.run_if(in_state(GameState::Loading)),
+                leave_loading_error.run_if(in_state(GameState::LoadingFailed)),
+                pause_course.run_if(in_state(GameState::Playing)),
+                handle_pause_menu
+                    .run_if(in_state(GameState::Paused).and(not(resource_exists::<SettingsMenu>))),
+                handle_results_menu.run_if(
+                    in_state(GameState::Results).and(not(resource_exists::<InstantReplay>)),
+                ),
+                setup_player.run_if(resource_added::<SelectedCharacter>),
+                (
+                    handle_emotes,
+                    handle_hands,
+                    carry_held_domino,
+                    handle_aiming,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing).and(not(resource_exists::<BuildPhase>)))
+                    .before(handle_animating),
+                build_animations.run_if(
+                    resource_exists::<CharacterGltf>.and(not(resource_exists::<Animations>)),
+                ),
+                attach_animation_graph.run_if(resource_exists::<Animations>),
+                (
+                    detect_knockdowns,
+                    recover_from_knockdown,
+                    handle_emote_wheel.run_if(in_state(GameState::Playing)),
+                    cheer_on_course_complete,
+                    handle_animating,
+                )
+                    .chain(),
+                orbit_camera.run_if(
+                    in_state(GameState::Playing)
+                        .or(in_state(GameState::Results))
+                        .and(not(resource_exists::<InstantReplay>))
+                        .and(not(resource_exists::<EmoteWheel>)),
+                ),
+                (toggle_debug_views, update_debug_panel).chain(),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                (start_replay, play_replay).chain(),
+                (
+                    detect_triggers,
+                    run_trigger_actions,
+                    open_gates,
+                    update_course_timer.run_if(not(resource_exists::<InstantReplay>)),
+                )
+                    .chain(),
+                (
+                    enter_puzzle.run_if(
+                        not(resource_exists::<BuildPhase>)
+                            .and(not(resource_exists::<InstantReplay>)),
+                    ),
+                    (build_puzzle, update_build_hud, start_puzzle)
+                        .chain()
+                        .run_if(in_state(GameState::Playing).and(resource_exists::<BuildPhase>)),
+                )
+                    .chain(),
+                // The replay moves the pieces back through the run it shows.
+                evaluate_goals.run_if(
+                    in_state(GameState::Playing)
+                        .and(not(resource_exists::<CourseResult>))
+                        .and(not(resource_exists::<InstantReplay>)),
+                ),
+            )
+                .run_if(in_state(GameState::Playing).or(in_state(GameState::Results))),
+        )
+        .add_systems(
+            PostUpdate,
+            follow_locomotion_weight
+                .after(bevy::animation::transition::advance_transitions)
+                .before(bevy::animation::animate_targets),
+        )
+        .add_systems(
+            PostUpdate,
+            (apply_foot_ik, pose_ragdoll)
+                .chain()
+                .after(AnimationSystems)
+                .before(TransformSystems::Propagate),
         )
-        .add_systems(Update, (handle_animating, orbit_camera))
         .run();
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 enum DominoType {
     Domino,
     Ball,
@@ -133,36 +304,594 @@ struct DominoElement {
     kind: DominoType,
 }
 
-fn setup_domino_scene(
+/// Marks every dynamic piece of the chain (dominoes and balls).
+#[derive(Component)]
+struct Domino;
+
+/// Which way was up for a course piece as it was set up. It has toppled once it no longer points
+/// that way, so pieces laid flat on purpose don't count as fallen from the start.
+#[derive(Component)]
+struct Upright(Vec3);
+
+impl Upright {
+    fn toppled(&self, transform: &Transform) -> bool {
+        transform.up().dot(self.0) < 0.5
+    }
+}
+
+/// Marks the last domino of the merge line. The chain is over once it falls.
+#[derive(Component)]
+struct FinalDomino;
+
+#[derive(Component)]
+struct Ball;
+
+#[derive(States, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+enum GameState {
+    /// Choosing a course, and a character the first time round.
+    #[default]
+    MainMenu,
+    /// Waiting for the character's model, texture, clips and tuning to load.
+    Loading,
+    /// Something the character needs failed to load.
+    LoadingFailed,
+    Playing,
+    /// The game clock is stopped, physics included.
+    Paused,
+    /// A run of the course is over and the completion screen is up.
+    Results,
+}
+
+/// Active while a course is spawned, whether it is being played, paused or looked back on.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct InCourse;
+
+impl ComputedStates for InCourse {
+    type SourceStates = GameState;
+
+    fn compute(state: GameState) -> Option<Self> {
+        match state {
+            GameState::Playing | GameState::Paused | GameState::Results => Some(InCourse),
+            GameState::MainMenu | GameState::Loading | GameState::LoadingFailed => None,
+        }
+    }
+}
+
+/// A static block a course is built on.
+struct Block {
+    center: Vec3,
+    size: Vec3,
+}
+
+impl Block {
+    const fn new(center: Vec3, size: Vec3) -> Self {
+        Self { center, size }
+    }
+}
+
+/// Spawns a course's trigger zones, gates and props.
+type CourseEvents = fn(&mut Commands, &mut Assets<Mesh>, &mut Assets<StandardMaterial>);
+
+/// A belt that moves what lies on it along `direction`, possibly riding a lift.
+struct Conveyor {
+    center: Vec3,
+    size: Vec3,
+    direction: Vec3,
+    speed: f32,
+    lift: Option<Lift>,
+}
+
+/// Carries a belt through `waypoints`, the first of which is where it starts. Rails along its
+/// sides in z keep what rides it from rolling off.
+struct Lift {
+    waypoints: &'static [Vec3],
+    speed: f32,
+    /// How long it stops at each waypoint, in seconds.
+    stop: f32,
+}
+
+struct CourseDefinition {
+    name: &'static str,
+    /// Every piece of the course, in chain order. The last one is the [`FinalDomino`].
+    elements: fn() -> Vec<DominoElement>,
+    blocks: &'static [Block],
+    conveyors: &'static [Conveyor],
+    /// The first goal has to be [`Goal::ToppleFinal`].
+    goals: &'static [Goal],
+    puzzle: Option<Puzzle>,
+    events: Option<CourseEvents>,
+}
+
+const COURSES: [CourseDefinition; 6] = [
+    CourseDefinition {
+        name: "Stairs and split",
+        elements: stairs_course,
+        blocks: &[
+            // High platform
+            Block::new(Vec3::new(5.0, 2.0, -6.0), Vec3::new(4.0, 1.0, 6.0)),
+            // Steps, bottom at 0
+            Block::new(Vec3::new(5.0, 1.0, -2.5), Vec3::new(2.0, 2.0, 1.0)),
+            Block::new(Vec3::new(5.0, 0.8, -1.5), Vec3::new(2.0, 1.6, 1.0)),
+            Block::new(Vec3::new(5.0, 0.6, -0.5), Vec3::new(2.0, 1.2, 1.0)),
+            Block::new(Vec3::new(5.0, 0.4, 0.5), Vec3::new(2.0, 0.8, 1.0)),
+            Block::new(Vec3::new(5.0, 0.2, 1.5), Vec3::new(2.0, 0.4, 1.0)),
+        ],
+        conveyors: &[],
+        goals: &[
+            Goal::ToppleFinal,
+            Goal::BallInTarget,
+            Goal::ToppleAllWithin(20.0),
+        ],
+        puzzle: Some(Puzzle {
+            gaps: &[8..10, 13..16, 21..23],
+            dominoes: 8,
+            balls: 1,
+        }),
+        events: Some(stairs_course_events),
+    },
+    CourseDefinition {
+        name: "Spiral",
+        elements: spiral_course,
+        blocks: &[],
+        conveyors: &[],
+        goals: &[Goal::ToppleFinal, Goal::ToppleAllWithin(25.0)],
+        puzzle: None,
+        events: None,
+    },
+    CourseDefinition {
+        name: "Tower",
+        elements: tower_course,
+        blocks: &[],
+        conveyors: &[],
+        goals: &[Goal::ToppleFinal, Goal::ToppleAllWithin(10.0)],
+        puzzle: None,
+        events: None,
+    },
+    CourseDefinition {
+        name: "Staircase descent",
+        elements: staircase_course,
+        blocks: &[
+            // Landing at the top
+            Block::new(Vec3::new(16.5, 2.0, 10.0), Vec3::new(4.0, 4.0, 2.0)),
+            Block::new(Vec3::new(14.0, 1.75, 10.0), Vec3::new(1.0, 3.5, 2.0)),
+            Block::new(Vec3::new(13.0, 1.5, 10.0), Vec3::new(1.0, 3.0, 2.0)),
+            Block::new(Vec3::new(12.0, 1.25, 10.0), Vec3::new(1.0, 2.5, 2.0)),
+            Block::new(Vec3::new(11.0, 1.0, 10.0), Vec3::new(1.0, 2.0, 2.0)),
+            Block::new(Vec3::new(10.0, 0.75, 10.0), Vec3::new(1.0, 1.5, 2.0)),
+            Block::new(Vec3::new(9.0, 0.5, 10.0), Vec3::new(1.0, 1.0, 2.0)),
+            Block::new(Vec3::new(8.0, 0.25, 10.0), Vec3::new(1.0, 0.5, 2.0)),
+        ],
+        conveyors: &[],
+        goals: &[Goal::ToppleFinal, Goal::ToppleAllWithin(15.0)],
+        puzzle: None,
+        events: None,
+    },
+    CourseDefinition {
+        name: "Multi-ball",
+        elements: multi_ball_course,
+        blocks: &[],
+        conveyors: &[],
+        goals: &[Goal::ToppleFinal, Goal::ToppleAllWithin(20.0)],
+        puzzle: None,
+        events: None,
+    },
+    CourseDefinition {
+        name: "Lift",
+        elements: lift_course,
+        blocks: &[
+            // Closes off the space under the high platform's edge, for the lift's belt to push
+            // against.
+            Block::new(Vec3::new(-1.05, 0.75, 3.0), Vec3::new(0.1, 1.5, 2.0)),
+        ],
+        conveyors: &[
+            // A lift up the east face of the high platform. Its deck is a belt that pushes
+            // whatever rides it against the platform, and over onto it once at the top.
+            Conveyor {
+                center: Vec3::new(0.05, 0.1, 3.0),
+                size: Vec3::new(2.0, 0.2, 2.0),
+                direction: Vec3::NEG_X,
+                speed: 1.0,
+                lift: Some(Lift {
+                    waypoints: &[Vec3::new(0.05, 0.1, 3.0), Vec3::new(0.05, 2.4, 3.0)],
+                    speed: 1.0,
+                    stop: 2.0,
+                }),
+            },
+            // A belt feeding into the bottom of the lift.
+            Conveyor {
+                center: Vec3::new(2.35, 0.05, 3.0),
+                size: Vec3::new(2.4, 0.1, 1.6),
+                direction: Vec3::NEG_X,
+                speed: 2.0,
+                lift: None,
+            },
+        ],
+        goals: &[Goal::ToppleFinal, Goal::ToppleAllWithin(20.0)],
+        puzzle: None,
+        events: None,
+    },
+];
+
+#[derive(Resource)]
+struct SelectedCourse(usize);
+
+impl SelectedCourse {
+    fn definition(&self) -> &'static CourseDefinition {
+        &COURSES[self.0]
+    }
+}
+
+#[derive(Component)]
+struct MainMenu;
+
+fn setup_main_menu(mut commands: Commands) {
+    commands.remove_resource::<SelectedCourse>();
+    commands.remove_resource::<PuzzleMode>();
+    let choices = COURSES
+        .iter()
+        .enumerate()
+        .map(|(i, course)| format!("{} - {}", i + 1, course.name))
+        .collect::<Vec<_>>();
+    commands.spawn((
+        MainMenu,
+        DespawnOnExit(GameState::MainMenu),
+        Text::new(format!(
+            "Choose a course:\n{}\n\nS - settings",
+            choices.join("\n")
+        )),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
+    ));
+}
+
+/// Picks a course, then moves on to picking a character unless one was picked already.
+fn choose_course(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    character: Option<Res<SelectedCharacter>>,
+    menu: Query<Entity, With<MainMenu>>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
-    let platform_mat = materials.add(Color::from(css::DARK_SLATE_GRAY));
+    const KEYS: [KeyCode; 6] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+    ];
+    let Some(index) = KEYS
+        .iter()
+        .take(COURSES.len())
+        .position(|key| keyboard.just_pressed(*key))
+    else {
+        return;
+    };
+    info!("Playing {}", COURSES[index].name);
+    commands.insert_resource(SelectedCourse(index));
+    if character.is_some() {
+        next_state.set(GameState::Loading);
+        return;
+    }
+    for entity in &menu {
+        commands.entity(entity).despawn();
+    }
+    spawn_character_select(&mut commands);
+}
+
+/// Everything the selected character needs before the course can start.
+#[derive(Resource)]
+struct CharacterAssets {
+    model: Handle<Gltf>,
+    texture: Option<Handle<Image>>,
+    control_scheme: Handle<ControlSchemeConfig>,
+}
+
+#[derive(Component)]
+struct LoadingText;
+
+#[derive(Component)]
+struct LoadingBar;
+
+fn setup_loading_screen(mut commands: Commands) {
+    commands
+        .spawn((
+            DespawnOnExit(GameState::Loading),
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((LoadingText, Text::new("Loading...")));
+            parent
+                .spawn((
+                    Node {
+                        width: Val::Px(300.0),
+                        height: Val::Px(12.0),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+                ))
+                .with_children(|bar| {
+                    bar.spawn((
+                        LoadingBar,
+                        Node {
+                            width: Val::Percent(0.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        BackgroundColor(css::GOLD.into()),
+                    ));
+                });
+        });
+}
 
-    // High platform
+/// The reason the last load failed, for the error screen.
+#[derive(Resource)]
+struct LoadingError(String);
+
+/// Counts the character's assets as they load, and starts the course once all of them have and
+/// the animation graph is built.
+#[allow(clippy::too_many_arguments)]
+fn track_loading(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    character: Res<SelectedCharacter>,
+    character_assets: Option<Res<CharacterAssets>>,
+    gltfs: Res<Assets<Gltf>>,
+    animations: Option<Res<Animations>>,
+    mut text: Single<&mut Text, With<LoadingText>>,
+    mut bar: Single<&mut Node, With<LoadingBar>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let character = character.definition();
+    let Some(character_assets) = character_assets else {
+        // Loaded for an earlier course already.
+        if animations.is_some() {
+            next_state.set(GameState::Playing);
+        }
+        return;
+    };
+
+    let mut assets = vec![
+        (
+            character.model_path.to_string(),
+            character_assets.model.id().untyped(),
+        ),
+        (
+            character.control_scheme.to_string(),
+            character_assets.control_scheme.id().untyped(),
+        ),
+    ];
+    if let (Some(path), Some(texture)) = (character.texture, &character_assets.texture) {
+        assets.push((path.to_string(), texture.id().untyped()));
+    }
+    // The clips are only known once the model is in, until then expect one for each animation.
+    let clips = gltfs.get(&character_assets.model).map(|gltf| {
+        gltf.named_animations
+            .iter()
+            .map(|(name, clip)| (format!("{name} clip"), clip.id().untyped()))
+            .collect::<Vec<_>>()
+    });
+    let pending_clips = if clips.is_some() { 0 } else { ANIMATIONS.len() };
+    assets.extend(clips.into_iter().flatten());
+
+    let mut loaded = 0;
+    for (name, id) in &assets {
+        match asset_server.get_load_state(*id) {
+            Some(LoadState::Loaded) => loaded += 1,
+            Some(LoadState::Failed(err)) => {
+                error!("Failed to load {name}: {err}");
+                commands.insert_resource(LoadingError(format!("{name}: {err}")));
+                next_state.set(GameState::LoadingFailed);
+                return;
+            }
+            _ => {}
+        }
+    }
+
+    let total = assets.len() + pending_clips;
+    text.0 = format!("Loading {}... {loaded}/{total}", character.name);
+    bar.width = Val::Percent(100.0 * loaded as f32 / total as f32);
+
+    if loaded == total && animations.is_some() {
+        commands.remove_resource::<CharacterAssets>();
+        next_state.set(GameState::Playing);
+    }
+}
+
+fn setup_loading_error_screen(mut commands: Commands, error: Res<LoadingError>) {
     commands.spawn((
-        Mesh3d(meshes.add(Cuboid::new(4.0, 1.0, 6.0))),
-        MeshMaterial3d(platform_mat.clone()),
-        Transform::from_xyz(5.0, 2.0, -6.0),
-        RigidBody::Static,
-        Collider::cuboid(4.0, 1.0, 6.0),
+        DespawnOnExit(GameState::LoadingFailed),
+        Text::new(format!(
+            "Loading failed\n\n{}\n\nEsc - back to the main menu",
+            error.0
+        )),
+        TextColor(css::TOMATO.into()),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
     ));
+}
 
-    // Steps
-    for i in 0..5 {
-        let h = 2.0 - (i as f32) * 0.4;
-        let z = -2.5 + (i as f32) * 1.0;
+/// Goes back to the menu without the broken character, so another one can be picked.
+fn leave_loading_error(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    player: Query<Entity, With<Player>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    for entity in &player {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<SelectedCharacter>();
+    commands.remove_resource::<CharacterAssets>();
+    commands.remove_resource::<CharacterGltf>();
+    commands.remove_resource::<Animations>();
+    commands.remove_resource::<LoadingError>();
+    next_state.set(GameState::MainMenu);
+}
+
+fn pause_course(keyboard: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Paused);
+    }
+}
+
+#[derive(Component)]
+struct PauseMenu;
+
+/// Stops the virtual clock, which stops the fixed timestep and with it physics and the
+/// character controller.
+fn setup_pause_menu(mut commands: Commands, mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+    commands
+        .spawn((
+            PauseMenu,
+            DespawnOnExit(GameState::Paused),
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.4)),
+        ))
+        .with_children(|parent| {
+            parent.spawn(Text::new(
+                "Paused\n\nEsc - resume\nS - settings\nM - main menu",
+            ));
+        });
+}
+
+fn handle_pause_menu(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Playing);
+    } else if keyboard.just_pressed(KeyCode::KeyM) {
+        next_state.set(GameState::MainMenu);
+    }
+}
+
+fn resume_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
+
+/// Going through loading again leaves the course, so it is despawned and spawned afresh.
+fn handle_results_menu(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keyboard.just_pressed(KeyCode::Enter) {
+        next_state.set(GameState::Loading);
+    } else if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::MainMenu);
+    }
+}
+
+fn spawn_selected_course(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    domino_assets: Res<DominoAssets>,
+    course: Res<SelectedCourse>,
+    puzzle: Option<Res<PuzzleMode>>,
+    mut player: Query<(&mut Transform, &mut LinearVelocity), With<Player>>,
+) {
+    let course = course.definition();
+    let platform_mat = materials.add(Color::from(css::DARK_SLATE_GRAY));
+    for block in course.blocks {
         commands.spawn((
-            Mesh3d(meshes.add(Cuboid::new(2.0, h, 1.0))),
+            DespawnOnExit(InCourse),
+            Mesh3d(meshes.add(Cuboid::from_size(block.size))),
             MeshMaterial3d(platform_mat.clone()),
-            Transform::from_xyz(5.0, h / 2.0, z), // Bottom at 0
+            Transform::from_translation(block.center),
             RigidBody::Static,
-            Collider::cuboid(2.0, h, 1.0),
+            Collider::cuboid(block.size.x, block.size.y, block.size.z),
         ));
     }
+    for conveyor in course.conveyors {
+        spawn_conveyor(&mut commands, &mut meshes, &mut materials, conveyor);
+    }
+
+    let gaps = match (&course.puzzle, puzzle) {
+        (Some(course_puzzle), Some(_)) => course_puzzle.gaps,
+        _ => &[],
+    };
+    spawn_course(&mut commands, &domino_assets, course, gaps);
+    if let Some(events) = course.events {
+        events(&mut commands, &mut meshes, &mut materials);
+    }
+    commands.spawn((
+        CourseTimerText,
+        DespawnOnExit(InCourse),
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
+    ));
+
+    // Back to the start, in case the player is coming from another course.
+    if let Ok((mut transform, mut velocity)) = player.single_mut() {
+        *transform = Transform::from_xyz(0.0, 2.0, 0.0);
+        *velocity = LinearVelocity::ZERO;
+    }
+}
+
+/// Resets everything a run of a course leaves behind. Its entities, ragdolls included, despawn on
+/// their own.
+fn clear_course(
+    mut commands: Commands,
+    mut physics_time: ResMut<Time<Physics>>,
+    mut player: Query<(Entity, &mut Hands, &mut FallTracking), With<Player>>,
+) {
+    commands.remove_resource::<CourseResult>();
+    commands.remove_resource::<BuildPhase>();
+    commands.remove_resource::<InstantReplay>();
+    commands.remove_resource::<EmoteWheel>();
+    commands.insert_resource(CourseProgress::default());
+    commands.insert_resource(ChainRecording::default());
+    commands.insert_resource(CourseTimer::default());
+    physics_time.unpause();
+    for (entity, mut hands, mut fall) in &mut player {
+        *hands = Hands::default();
+        *fall = FallTracking::default();
+        // Knocked down, staggering or sitting: back on its feet for the next course.
+        commands.entity(entity).remove::<(
+            Knockdown,
+            Staggered,
+            Emoting,
+            RigidBodyDisabled,
+            ColliderDisabled,
+        )>();
+    }
+}
 
-    // --- Generate Domino Vector ---
+fn stairs_course() -> Vec<DominoElement> {
     let mut elements: Vec<DominoElement> = Vec::new();
     let domino_rot = Quat::from_rotation_y(FRAC_PI_2);
 
@@ -267,366 +996,4063 @@ fn setup_domino_scene(
         });
     }
 
-    // --- Spawn Elements ---
-    let domino_mesh = meshes.add(Cuboid::new(0.1, 1.0, 0.5));
-    let domino_mat = materials.add(Color::from(css::ORANGE_RED));
+    elements
+}
 
-    let ball_mesh = meshes.add(Sphere::new(0.4));
-    let ball_mat = materials.add(Color::from(css::DODGER_BLUE));
+/// A spiral on the ground, winding inwards.
+fn spiral_course() -> Vec<DominoElement> {
+    const CENTER: Vec3 = Vec3::new(5.0, 0.5, 12.0);
+    const OUTER_RADIUS: f32 = 5.0;
+    const INNER_RADIUS: f32 = 1.5;
+    /// Distance between two turns of the spiral.
+    const TURN_SPACING: f32 = 1.2;
 
-    for elem in elements {
-        match elem.kind {
-            DominoType::Domino => {
-                commands.spawn((
-                    Mesh3d(domino_mesh.clone()),
-                    MeshMaterial3d(domino_mat.clone()),
-                    Transform::from_translation(elem.pos).with_rotation(elem.rot),
-                    RigidBody::Dynamic,
-                    Collider::cuboid(0.1, 1.0, 0.5),
-                    Mass(1.0),
-                    Friction::new(0.5),
-                ));
-            }
-            DominoType::Ball => {
-                commands.spawn((
-                    Mesh3d(ball_mesh.clone()),
-                    MeshMaterial3d(ball_mat.clone()),
-                    Transform::from_translation(elem.pos).with_rotation(elem.rot),
-                    RigidBody::Dynamic,
-                    Collider::sphere(0.4),
-                    Mass(2.0),
-                    Restitution::new(0.7), // Bouncy
-                ));
-            }
+    let mut elements = Vec::new();
+    let mut angle: f32 = 0.0;
+    loop {
+        let radius = OUTER_RADIUS - TURN_SPACING * angle / TAU;
+        if radius < INNER_RADIUS {
+            break;
         }
+        let (sin_a, cos_a) = angle.sin_cos();
+        let tangent = Vec3::new(-sin_a, 0.0, cos_a) * radius
+            - Vec3::new(cos_a, 0.0, sin_a) * TURN_SPACING / TAU;
+        elements.push(DominoElement {
+            pos: CENTER + Vec3::new(cos_a, 0.0, sin_a) * radius,
+            rot: Quat::from_rotation_arc(Vec3::X, tangent.normalize()),
+            kind: DominoType::Domino,
+        });
+        angle += 0.6 / radius;
     }
+    elements
 }
 
-#[derive(TnuaScheme)]
-#[scheme(basis = TnuaBuiltinWalk)]
-enum ControlScheme {
-    Jump(TnuaBuiltinJump),
-    Dash(TnuaBuiltinDash),
-}
+/// A line running into a tower of dominoes: standing pairs bridged by flat ones, with one more
+/// standing on top.
+fn tower_course() -> Vec<DominoElement> {
+    const TOWER_Z: f32 = 12.0;
+    const LEVELS: usize = 6;
+    /// One standing domino plus one lying on top of it.
+    const LEVEL_HEIGHT: f32 = 1.1;
 
-impl Default for ControlSchemeConfig {
-    fn default() -> Self {
-        ControlSchemeConfig {
-            basis: TnuaBuiltinWalkConfig {
-                speed: 10.0,
-                turning_angvel: 15.0,
-                float_height: 0.01,
-                ..default()
-            },
-            jump: TnuaBuiltinJumpConfig {
-                height: 5.0,
-                ..default()
-            },
-            dash: TnuaBuiltinDashConfig {
-                horizontal_distance: 5.0,
-                vertical_distance: 0.0,
-                ..default()
-            },
+    let domino_rot = Quat::from_rotation_y(FRAC_PI_2);
+    let flat_rot = Quat::from_rotation_z(FRAC_PI_2);
+    let mut elements: Vec<DominoElement> = (0..10)
+        .map(|i| DominoElement {
+            pos: Vec3::new(5.0, 0.5, TOWER_Z - 0.6 - (9 - i) as f32 * 0.6),
+            rot: domino_rot,
+            kind: DominoType::Domino,
+        })
+        .collect();
+
+    for level in 0..LEVELS {
+        let base = level as f32 * LEVEL_HEIGHT;
+        for x in [4.6, 5.4] {
+            elements.push(DominoElement {
+                pos: Vec3::new(x, base + 0.5, TOWER_Z),
+                rot: domino_rot,
+                kind: DominoType::Domino,
+            });
         }
+        elements.push(DominoElement {
+            pos: Vec3::new(5.0, base + 1.05, TOWER_Z),
+            rot: flat_rot,
+            kind: DominoType::Domino,
+        });
     }
+
+    elements.push(DominoElement {
+        pos: Vec3::new(5.0, LEVELS as f32 * LEVEL_HEIGHT + 0.5, TOWER_Z),
+        rot: domino_rot,
+        kind: DominoType::Domino,
+    });
+    elements
 }
 
-pub struct SlowDownWhileCrouching(pub bool);
+/// Down a long flight of steps, one domino per step.
+fn staircase_course() -> Vec<DominoElement> {
+    let mut elements = Vec::new();
 
-impl TnuaConfigModifier<TnuaBuiltinWalkConfig> for SlowDownWhileCrouching {
-    fn modify_config(&self, config: &mut TnuaBuiltinWalkConfig) {
-        if self.0 {
-            config.speed *= 0.5;
+    // Landing at the top
+    for i in 0..6 {
+        elements.push(DominoElement {
+            pos: Vec3::new(18.0 - i as f32 * 0.6, 4.5, 10.0),
+            rot: Quat::IDENTITY,
+            kind: DominoType::Domino,
+        });
+    }
+
+    // Steps
+    for i in 0..7 {
+        let h = 3.5 - i as f32 * 0.5;
+        elements.push(DominoElement {
+            pos: Vec3::new(14.0 - i as f32, h + 0.5, 10.0),
+            rot: Quat::IDENTITY,
+            kind: DominoType::Domino,
+        });
+    }
+
+    // Off along the ground
+    for i in 0..8 {
+        elements.push(DominoElement {
+            pos: Vec3::new(7.0 - i as f32 * 0.6, 0.5, 10.0),
+            rot: Quat::IDENTITY,
+            kind: DominoType::Domino,
+        });
+    }
+    elements
+}
+
+/// Three balls, each rolled on by one line of dominoes into the next.
+fn multi_ball_course() -> Vec<DominoElement> {
+    let domino_rot = Quat::from_rotation_y(FRAC_PI_2);
+    let mut elements = Vec::new();
+    let mut z = 0.0;
+
+    for line in 0..4 {
+        for _ in 0..6 {
+            elements.push(DominoElement {
+                pos: Vec3::new(5.0, 0.5, z),
+                rot: domino_rot,
+                kind: DominoType::Domino,
+            });
+            z += 0.6;
+        }
+        if line == 3 {
+            break;
         }
+        // Same spacing as the ball in the middle of the stairs course.
+        z += 0.6;
+        elements.push(DominoElement {
+            pos: Vec3::new(5.0, 0.4, z),
+            rot: Quat::IDENTITY,
+            kind: DominoType::Ball,
+        });
+        z += 0.9;
     }
+    elements
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum AnimationState {
-    Standing,
-    Running(f32),
-    Jumping,
-    Falling,
-    Landing,
-    Dashing,
+/// A ball on the lift, carried up to a line of dominoes across the high platform.
+fn lift_course() -> Vec<DominoElement> {
+    let mut elements = vec![DominoElement {
+        pos: Vec3::new(0.0, 0.6, 3.0),
+        rot: Quat::IDENTITY,
+        kind: DominoType::Ball,
+    }];
+    for i in 0..14 {
+        elements.push(DominoElement {
+            pos: Vec3::new(-2.0 - i as f32 * 0.6, 3.0, 3.0),
+            rot: Quat::IDENTITY,
+            kind: DominoType::Domino,
+        });
+    }
+    elements
 }
 
 #[derive(Resource)]
-struct Animations {
+struct DominoAssets {
+    domino_mesh: Handle<Mesh>,
+    domino_material: Handle<StandardMaterial>,
+    ball_mesh: Handle<Mesh>,
+    ball_material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for DominoAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            domino_mesh: world.add_asset(Cuboid::new(0.1, 1.0, 0.5)),
+            domino_material: world.add_asset(StandardMaterial::from(Color::from(css::ORANGE_RED))),
+            ball_mesh: world.add_asset(Sphere::new(0.4)),
+            ball_material: world.add_asset(StandardMaterial::from(Color::from(css::DODGER_BLUE))),
+        }
+    }
+}
+
+/// Marks the first domino of the chain, which gets the push when a puzzle is started.
+#[derive(Component)]
+struct FirstDomino;
+
+/// Spawns the pieces of a course, leaving out the ones in `gaps` (ranges of indices into its
+/// elements).
+fn spawn_course(
+    commands: &mut Commands,
+    assets: &DominoAssets,
+    course: &CourseDefinition,
+    gaps: &[Range<usize>],
+) {
+    let elements = (course.elements)();
+    let final_index = elements.len() - 1;
+    for (i, elem) in elements.iter().enumerate() {
+        if gaps.iter().any(|gap| gap.contains(&i)) {
+            continue;
+        }
+        let mut piece = spawn_piece(commands, assets, elem);
+        if i == 0 {
+            piece.insert(FirstDomino);
+        }
+        if i == final_index {
+            piece.insert(FinalDomino);
+        }
+    }
+}
+
+impl DominoType {
+    fn collider(self) -> Collider {
+        match self {
+            DominoType::Domino => Collider::cuboid(0.1, 1.0, 0.5),
+            DominoType::Ball => Collider::sphere(0.4),
+        }
+    }
+}
+
+fn spawn_piece<'a>(
+    commands: &'a mut Commands,
+    assets: &DominoAssets,
+    elem: &DominoElement,
+) -> EntityCommands<'a> {
+    let transform = Transform::from_translation(elem.pos).with_rotation(elem.rot);
+    let upright = Upright(elem.rot * Vec3::Y);
+    match elem.kind {
+        DominoType::Domino => commands.spawn((
+            Domino,
+            upright,
+            DespawnOnExit(InCourse),
+            Mesh3d(assets.domino_mesh.clone()),
+            MeshMaterial3d(assets.domino_material.clone()),
+            transform,
+            RigidBody::Dynamic,
+            elem.kind.collider(),
+            Mass(1.0),
+            Friction::new(0.5),
+        )),
+        DominoType::Ball => commands.spawn((
+            Domino,
+            Ball,
+            upright,
+            DespawnOnExit(InCourse),
+            Mesh3d(assets.ball_mesh.clone()),
+            MeshMaterial3d(assets.ball_material.clone()),
+            transform,
+            RigidBody::Dynamic,
+            elem.kind.collider(),
+            Mass(2.0),
+            Restitution::new(0.7), // Bouncy
+        )),
+    }
+}
+
+/// A course with gaps in the chain and a budget of pieces to fill them with.
+struct Puzzle {
+    /// Ranges of indices into the course's elements that are left out.
+    gaps: &'static [Range<usize>],
+    dominoes: u32,
+    balls: u32,
+}
+
+/// Minimum gap between the surfaces of a placed piece and any other piece.
+const BUILD_CLEARANCE: f32 = 0.05;
+const BUILD_FOCUS_SPEED: f32 = 6.0;
+const FIRST_DOMINO_PUSH: f32 = 2.0;
+
+/// Present while the course is played as a puzzle, until the player goes back to the main menu.
+#[derive(Resource)]
+struct PuzzleMode;
+
+/// Present while the player is placing pieces. Physics is paused for the duration.
+#[derive(Resource)]
+struct BuildPhase {
+    dominoes: u32,
+    balls: u32,
+    piece: DominoType,
+    turns: i32,
+    /// What the camera orbits around.
+    focus: Vec3,
+}
+
+impl BuildPhase {
+    fn remaining(&mut self, kind: DominoType) -> &mut u32 {
+        match kind {
+            DominoType::Domino => &mut self.dominoes,
+            DominoType::Ball => &mut self.balls,
+        }
+    }
+}
+
+/// A piece placed by the player during the build phase, which may be taken back.
+#[derive(Component)]
+struct Placed;
+
+/// Translucent preview of the piece about to be placed.
+#[derive(Component)]
+struct BuildGhost;
+
+#[derive(Component)]
+struct BuildHud;
+
+/// Press P to (re)start the course's puzzle. Like playing again from the results, it goes
+/// through loading, so the course is cleared and spawned afresh - with gaps this time.
+fn enter_puzzle(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    course: Res<SelectedCourse>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if course.definition().puzzle.is_some() && keyboard.just_pressed(settings.key_bindings.puzzle) {
+        commands.insert_resource(PuzzleMode);
+        next_state.set(GameState::Loading);
+    }
+}
+
+/// Pauses physics on a freshly spawned puzzle course and hands out the pieces to fill it with.
+fn setup_build_phase(
+    mut commands: Commands,
+    assets: Res<DominoAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut physics_time: ResMut<Time<Physics>>,
+    course: Res<SelectedCourse>,
+    player: Query<&Transform, With<Player>>,
+) {
+    let Some(puzzle) = &course.definition().puzzle else {
+        return;
+    };
+    let Ok(player_transform) = player.single() else {
+        return;
+    };
+
+    physics_time.pause();
+    commands.insert_resource(BuildPhase {
+        dominoes: puzzle.dominoes,
+        balls: puzzle.balls,
+        piece: DominoType::Domino,
+        turns: 0,
+        focus: player_transform.translation,
+    });
+    commands.spawn((
+        BuildGhost,
+        DespawnOnExit(InCourse),
+        Mesh3d(assets.domino_mesh.clone()),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgba(1.0, 1.0, 1.0, 0.4),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        })),
+        Transform::default(),
+        Visibility::Hidden,
+    ));
+    commands.spawn((
+        BuildHud,
+        DespawnOnExit(InCourse),
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
+    ));
+}
+
+/// Places and removes pieces under the cursor while the build phase lasts.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn build_puzzle(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    time: Res<Time>,
+    mut spatial_query: SpatialQuery,
+    assets: Res<DominoAssets>,
+    mut phase: ResMut<BuildPhase>,
+    window: Single<&Window>,
+    camera: Single<(&Camera, &GlobalTransform, &ThirdPersonCamera)>,
+    mut ghost: Single<(&mut Transform, &mut Mesh3d, &mut Visibility), With<BuildGhost>>,
+    pieces: Query<
+        (Entity, &Transform, &Collider, Has<Ball>, Has<Placed>),
+        (With<Domino>, Without<BuildGhost>),
+    >,
+    bodies: Query<&RigidBody>,
+) {
+    let (camera, camera_transform, orbit) = *camera;
+
+    if keyboard.just_pressed(KeyCode::Tab) {
+        phase.piece = match phase.piece {
+            DominoType::Domino => DominoType::Ball,
+            DominoType::Ball => DominoType::Domino,
+        };
+    }
+    let bindings = &settings.key_bindings;
+    if keyboard.just_pressed(bindings.rotate_left) {
+        phase.turns -= 1;
+    }
+    if keyboard.just_pressed(bindings.rotate_right) {
+        phase.turns += 1;
+    }
+
+    let mut direction = Vec3::ZERO;
+    if keyboard.pressed(bindings.forward) {
+        direction -= Vec3::Z;
+    }
+    if keyboard.pressed(bindings.back) {
+        direction += Vec3::Z;
+    }
+    if keyboard.pressed(bindings.left) {
+        direction -= Vec3::X;
+    }
+    if keyboard.pressed(bindings.right) {
+        direction += Vec3::X;
+    }
+    phase.focus += Quat::from_rotation_y(orbit.yaw)
+        * direction.normalize_or_zero()
+        * BUILD_FOCUS_SPEED
+        * time.delta_secs();
+
+    // With physics paused the pipeline isn't updated by the physics step, so the course that was
+    // just spawned and the pieces placed since would be missing from it.
+    spatial_query.update_pipeline();
+
+    // Only the level itself can be built on: no pieces, platforms or characters.
+    let hit = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| {
+            let hit = spatial_query.cast_ray_predicate(
+                ray.origin.adjust_precision(),
+                ray.direction,
+                100.0,
+                true,
+                &solid_filter([]),
+                &|entity| bodies.get(entity).is_ok_and(RigidBody::is_static),
+            )?;
+            (hit.normal.y > 0.7).then(|| ray.get_point(hit.distance.f32()))
+        });
+
+    let (ghost_transform, ghost_mesh, ghost_visibility) = &mut *ghost;
+    let Some(point) = hit else {
+        **ghost_visibility = Visibility::Hidden;
+        return;
+    };
+    let (mesh, lift) = match phase.piece {
+        DominoType::Domino => (&assets.domino_mesh, 0.5),
+        DominoType::Ball => (&assets.ball_mesh, 0.4),
+    };
+    let position = point + Vec3::Y * lift;
+    let rotation = Quat::from_rotation_y(FRAC_PI_2 + PLACEMENT_SNAP * phase.turns as f32);
+    **ghost_visibility = Visibility::Visible;
+    ghost_mesh.0 = mesh.clone();
+    **ghost_transform = Transform::from_translation(position).with_rotation(rotation);
+
+    if mouse.just_pressed(MouseButton::Left) {
+        let piece = phase.piece;
+        let collider = piece.collider();
+        // Raised off the surface it stands on, the piece must not reach into the level, such as
+        // the next step up.
+        let in_level = spatial_query
+            .shape_intersections(
+                &collider,
+                (position + Vec3::Y * BUILD_CLEARANCE).adjust_precision(),
+                rotation.adjust_precision(),
+                &solid_filter([]),
+            )
+            .into_iter()
+            .any(|entity| bodies.get(entity).is_ok_and(RigidBody::is_static));
+        let clear = !in_level
+            && pieces.iter().all(|(_, transform, other, _, _)| {
+                contact_query::distance(
+                    &collider,
+                    position.adjust_precision(),
+                    rotation.adjust_precision(),
+                    other,
+                    transform.translation.adjust_precision(),
+                    transform.rotation.adjust_precision(),
+                )
+                .is_ok_and(|distance| distance.f32() > BUILD_CLEARANCE)
+            });
+        let remaining = phase.remaining(piece);
+        if clear && *remaining > 0 {
+            *remaining -= 1;
+            let elem = DominoElement {
+                pos: position,
+                rot: rotation,
+                kind: piece,
+            };
+            spawn_piece(&mut commands, &assets, &elem).insert(Placed);
+        }
+    } else if keyboard.just_pressed(KeyCode::Backspace) {
+        let nearest = pieces
+            .iter()
+            .filter(|(_, _, _, _, placed)| *placed)
+            .map(|(entity, transform, _, ball, _)| {
+                let kind = if ball {
+                    DominoType::Ball
+                } else {
+                    DominoType::Domino
+                };
+                (entity, kind, transform.translation.distance(position))
+            })
+            .filter(|(_, _, distance)| *distance < 0.5 + lift)
+            .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+        if let Some((entity, kind, _)) = nearest {
+            commands.entity(entity).despawn();
+            *phase.remaining(kind) += 1;
+        }
+    }
+}
+
+fn update_build_hud(
+    phase: Res<BuildPhase>,
+    settings: Res<Settings>,
+    mut hud: Single<&mut Text, With<BuildHud>>,
+) {
+    let bindings = &settings.key_bindings;
+    let piece = match phase.piece {
+        DominoType::Domino => "domino",
+        DominoType::Ball => "ball",
+    };
+    hud.0 = format!(
+        "Puzzle: fill the gaps so the chain reaches the end\n\
+         Dominoes left: {}  Balls left: {}  Placing: {piece}\n\
+         Click: place  Backspace: remove  Tab: switch piece  {:?}/{:?}: rotate\n\
+         {:?}/{:?}/{:?}/{:?}: move view  Right mouse: orbit  Enter: go!",
+        phase.dominoes,
+        phase.balls,
+        bindings.rotate_left,
+        bindings.rotate_right,
+        bindings.forward,
+        bindings.left,
+        bindings.back,
+        bindings.right,
+    );
+}
+
+/// Ends the build phase on Enter and gives the first domino a push.
+#[allow(clippy::type_complexity)]
+fn start_puzzle(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut physics_time: ResMut<Time<Physics>>,
+    build_ui: Query<Entity, Or<(With<BuildGhost>, With<BuildHud>)>>,
+    mut first_domino: Query<&mut AngularVelocity, With<FirstDomino>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Enter) {
+        return;
+    }
+    for entity in &build_ui {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<BuildPhase>();
+    physics_time.unpause();
+    for mut angular_velocity in &mut first_domino {
+        angular_velocity.0 = Vec3::X.adjust_precision() * FIRST_DOMINO_PUSH;
+    }
+}
+
+/// What may set off a [`TriggerZone`].
+#[derive(Clone, Copy, PartialEq)]
+enum TriggerSource {
+    Player,
+    Domino,
+    Ball,
+}
+
+#[derive(Clone, Copy)]
+enum TriggerAction {
+    /// Lowers the [`Gate`] with this name into the ground.
+    OpenGate(&'static str),
+    StartTimer,
+    StopTimer,
+    SpawnBall(Vec3),
+    /// Plays a short tone at this frequency, in Hz.
+    PlaySound(f32),
+    /// Counts towards [`Goal::BallInTarget`].
+    ReachTarget,
+}
+
+/// A sensor volume that runs its actions, once, when something it listens for enters it.
+#[derive(Component)]
+#[require(
+    Sensor,
+    CollisionEventsEnabled,
+    CollisionLayers::new(GameLayer::Trigger, LayerMask::ALL)
+)]
+struct TriggerZone {
+    source: TriggerSource,
+    actions: &'static [TriggerAction],
+}
+
+#[derive(Message)]
+struct TriggerFired {
+    action: TriggerAction,
+}
+
+/// A barrier that sinks into the ground once opened by a [`TriggerAction::OpenGate`].
+#[derive(Component)]
+struct Gate {
+    name: &'static str,
+    open: bool,
+}
+
+const GATE_HEIGHT: f32 = 1.2;
+const GATE_SPEED: f32 = 2.0;
+const CELEBRATION_BALL: Vec3 = Vec3::new(5.0, 4.0, 27.0);
+
+/// Times a run of the course, from one trigger to another.
+#[derive(Resource, Default)]
+struct CourseTimer {
+    stopwatch: Stopwatch,
+    running: bool,
+}
+
+#[derive(Component)]
+struct CourseTimerText;
+
+fn stairs_course_events(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    // Stepping up to the first domino starts the clock.
+    commands.spawn((
+        DespawnOnExit(InCourse),
+        TriggerZone {
+            source: TriggerSource::Player,
+            actions: &[TriggerAction::StartTimer, TriggerAction::PlaySound(440.0)],
+        },
+        Transform::from_xyz(5.0, 3.5, -4.0),
+        Collider::cuboid(4.0, 1.0, 1.0),
+    ));
+
+    // Once the ball in the middle of the chain gets rolling, it opens the gate at the end of the
+    // line - too late, and the last domino has nowhere to fall.
+    commands.spawn((
+        DespawnOnExit(InCourse),
+        TriggerZone {
+            source: TriggerSource::Ball,
+            actions: &[
+                TriggerAction::OpenGate("finish"),
+                TriggerAction::PlaySound(660.0),
+            ],
+        },
+        Transform::from_xyz(5.0, 0.5, 8.55),
+        Collider::cuboid(1.0, 1.0, 0.4),
+    ));
+    commands.spawn((
+        DespawnOnExit(InCourse),
+        Gate {
+            name: "finish",
+            open: false,
+        },
+        Mesh3d(meshes.add(Cuboid::new(2.0, GATE_HEIGHT, 0.1))),
+        MeshMaterial3d(materials.add(Color::from(css::STEEL_BLUE))),
+        Transform::from_xyz(5.0, GATE_HEIGHT / 2.0, 25.0),
+        RigidBody::Kinematic,
+        Collider::cuboid(2.0, GATE_HEIGHT, 0.1),
+    ));
+
+    // The last domino landing past the gate stops the clock and drops in a ball to celebrate.
+    commands.spawn((
+        DespawnOnExit(InCourse),
+        TriggerZone {
+            source: TriggerSource::Domino,
+            actions: &[
+                TriggerAction::StopTimer,
+                TriggerAction::SpawnBall(CELEBRATION_BALL),
+                TriggerAction::PlaySound(880.0),
+            ],
+        },
+        Transform::from_xyz(5.0, 0.25, 25.6),
+        Collider::cuboid(2.0, 0.5, 0.6),
+    ));
+
+    // The target for the ball: the middle of the split.
+    commands.spawn((
+        DespawnOnExit(InCourse),
+        TriggerZone {
+            source: TriggerSource::Ball,
+            actions: &[TriggerAction::ReachTarget, TriggerAction::PlaySound(990.0)],
+        },
+        Transform::from_xyz(5.0, 0.5, 15.4),
+        Collider::cuboid(1.6, 1.0, 1.6),
+    ));
+    commands.spawn((
+        DespawnOnExit(InCourse),
+        Mesh3d(meshes.add(Cylinder::new(0.8, 0.02))),
+        MeshMaterial3d(materials.add(Color::from(css::GOLD))),
+        Transform::from_xyz(5.0, 0.01, 15.4),
+    ));
+}
+
+#[allow(clippy::type_complexity)]
+fn detect_triggers(
+    mut commands: Commands,
+    mut collision_starts: MessageReader<CollisionStart>,
+    mut fired: MessageWriter<TriggerFired>,
+    zones: Query<&TriggerZone>,
+    sources: Query<(Has<Player>, Has<Domino>, Has<Ball>), Without<ThrownBall>>,
+) {
+    // Despawning the zone is deferred, so several things entering it in the same batch would each
+    // fire it.
+    let mut fired_zones = HashSet::new();
+    for collision in collision_starts.read() {
+        let (zone, other) = if zones.contains(collision.collider1) {
+            (
+                collision.collider1,
+                collision.body2.unwrap_or(collision.collider2),
+            )
+        } else if zones.contains(collision.collider2) {
+            (
+                collision.collider2,
+                collision.body1.unwrap_or(collision.collider1),
+            )
+        } else {
+            continue;
+        };
+        let (Ok(trigger), Ok((player, domino, ball))) = (zones.get(zone), sources.get(other))
+        else {
+            continue;
+        };
+        let source = if player {
+            TriggerSource::Player
+        } else if ball {
+            TriggerSource::Ball
+        } else if domino {
+            TriggerSource::Domino
+        } else {
+            continue;
+        };
+        if source != trigger.source || !fired_zones.insert(zone) {
+            continue;
+        }
+        fired.write_batch(
+            trigger
+                .actions
+                .iter()
+                .map(|&action| TriggerFired { action }),
+        );
+        commands.entity(zone).despawn();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_trigger_actions(
+    mut commands: Commands,
+    mut fired: MessageReader<TriggerFired>,
+    mut gates: Query<&mut Gate>,
+    mut timer: ResMut<CourseTimer>,
+    mut progress: ResMut<CourseProgress>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut pitches: ResMut<Assets<Pitch>>,
+    settings: Res<Settings>,
+) {
+    for TriggerFired { action } in fired.read() {
+        match *action {
+            TriggerAction::OpenGate(name) => {
+                for mut gate in &mut gates {
+                    if gate.name == name {
+                        gate.open = true;
+                    }
+                }
+            }
+            TriggerAction::StartTimer => {
+                timer.stopwatch.reset();
+                timer.running = true;
+            }
+            TriggerAction::StopTimer => timer.running = false,
+            TriggerAction::SpawnBall(position) => {
+                commands.spawn((
+                    Ball,
+                    DespawnOnExit(InCourse),
+                    Mesh3d(meshes.add(Sphere::new(0.4))),
+                    MeshMaterial3d(materials.add(Color::from(css::GOLD))),
+                    Transform::from_translation(position),
+                    RigidBody::Dynamic,
+                    Collider::sphere(0.4),
+                    Mass(2.0),
+                    Restitution::new(0.7),
+                ));
+            }
+            TriggerAction::ReachTarget => progress.ball_in_target = true,
+            TriggerAction::PlaySound(frequency) => {
+                commands.spawn((
+                    AudioPlayer(pitches.add(Pitch::new(frequency, Duration::from_millis(250)))),
+                    PlaybackSettings::DESPAWN.with_volume(Volume::Linear(settings.effects_volume)),
+                ));
+            }
+        }
+    }
+}
+
+fn open_gates(mut gates: Query<(&Gate, &Position, &mut LinearVelocity)>) {
+    for (gate, position, mut velocity) in &mut gates {
+        let sinking = gate.open && -GATE_HEIGHT / 2.0 < position.y.f32();
+        velocity.0 = if sinking {
+            Vec3::NEG_Y * GATE_SPEED
+        } else {
+            Vec3::ZERO
+        }
+        .adjust_precision();
+    }
+}
+
+/// Runs on physics time, so it stands still while the replay or the build phase pauses physics.
+fn update_course_timer(
+    physics_time: Res<Time<Physics>>,
+    mut last_elapsed: Local<Duration>,
+    mut timer: ResMut<CourseTimer>,
+    mut text: Single<&mut Text, With<CourseTimerText>>,
+) {
+    let elapsed = physics_time.elapsed();
+    let delta = elapsed.saturating_sub(*last_elapsed);
+    *last_elapsed = elapsed;
+    if timer.running {
+        timer.stopwatch.tick(delta);
+    }
+    let elapsed = timer.stopwatch.elapsed_secs();
+    if timer.running || 0.0 < elapsed {
+        text.0 = format!("Time: {elapsed:.2} s");
+    }
+}
+
+/// Something to aim for on a course. The first goal of a course has to be met to complete it,
+/// the others earn extra stars.
+#[derive(Clone, Copy)]
+enum Goal {
+    /// Topple the [`FinalDomino`].
+    ToppleFinal,
+    /// Get a ball into a trigger zone with [`TriggerAction::ReachTarget`].
+    BallInTarget,
+    /// Topple every domino within this many seconds of the first one moving.
+    ToppleAllWithin(f32),
+}
+
+impl Goal {
+    fn description(&self) -> String {
+        match self {
+            Goal::ToppleFinal => "Topple the last domino".to_string(),
+            Goal::BallInTarget => "Roll the ball onto the target".to_string(),
+            Goal::ToppleAllWithin(seconds) => {
+                format!("Topple every domino within {seconds:.0} seconds")
+            }
+        }
+    }
+}
+
+/// How long nothing has to move before a run counts as over. Toppling the final domino doesn't end
+/// it early, so the rest of the chain still counts towards the other goals.
+const COURSE_SETTLE_TIME: f32 = 3.0;
+
+#[derive(Resource, Default)]
+struct CourseProgress {
+    /// When the first domino moved, in seconds of physics time.
+    chain_start: Option<f32>,
+    /// When any domino last moved, in seconds of physics time.
+    last_moved: f32,
+    ball_in_target: bool,
+    all_toppled_in: Option<f32>,
+    /// Whether the [`FinalDomino`] has fallen at any point.
+    final_fallen: bool,
+}
+
+impl CourseProgress {
+    fn met(&self, goal: Goal) -> bool {
+        match goal {
+            Goal::ToppleFinal => self.final_fallen,
+            Goal::BallInTarget => self.ball_in_target,
+            Goal::ToppleAllWithin(seconds) => self.all_toppled_in.is_some_and(|t| t <= seconds),
+        }
+    }
+}
+
+/// Inserted once a run of the course is over.
+#[derive(Resource)]
+struct CourseResult {
+    met: Vec<bool>,
+}
+
+impl CourseResult {
+    fn success(&self) -> bool {
+        self.met.first().copied().unwrap_or(false)
+    }
+
+    /// One star for completing the course, and one for each other goal met along the way.
+    fn stars(&self) -> usize {
+        if self.success() {
+            self.met.iter().filter(|met| **met).count()
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Component)]
+struct CompletionScreen;
+
+#[allow(clippy::type_complexity)]
+fn evaluate_goals(
+    mut commands: Commands,
+    physics_time: Res<Time<Physics>>,
+    mut progress: ResMut<CourseProgress>,
+    course: Res<SelectedCourse>,
+    mut next_state: ResMut<NextState<GameState>>,
+    // A piece in the player's hands can't topple, so it's left out until it is set down again.
+    dominoes: Query<
+        (&Transform, &Upright, &LinearVelocity, Has<Ball>),
+        (With<Domino>, Without<ThrownBall>, Without<Held>),
+    >,
+    final_domino: Query<(&Transform, &Upright), With<FinalDomino>>,
+) {
+    let now = physics_time.elapsed_secs();
+    let moving = dominoes
+        .iter()
+        .any(|(_, _, velocity, _)| velocity.length() > CHAIN_START_VELOCITY);
+    let Some(chain_start) = progress.chain_start.or(moving.then_some(now)) else {
+        return;
+    };
+    progress.chain_start = Some(chain_start);
+    if moving {
+        progress.last_moved = now;
+    }
+
+    let all_toppled = dominoes
+        .iter()
+        .filter(|(_, _, _, ball)| !ball)
+        .all(|(transform, upright, _, _)| upright.toppled(transform));
+    if all_toppled && progress.all_toppled_in.is_none() {
+        progress.all_toppled_in = Some(now - chain_start);
+    }
+
+    progress.final_fallen |= final_domino
+        .single()
+        .is_ok_and(|(transform, upright)| upright.toppled(transform));
+    if COURSE_SETTLE_TIME < now - progress.last_moved {
+        let met = course
+            .definition()
+            .goals
+            .iter()
+            .map(|goal| progress.met(*goal))
+            .collect();
+        commands.insert_resource(CourseResult { met });
+        next_state.set(GameState::Results);
+    }
+}
+
+fn show_completion_screen(
+    mut commands: Commands,
+    result: Res<CourseResult>,
+    course: Res<SelectedCourse>,
+    puzzle: Option<Res<PuzzleMode>>,
+    settings: Res<Settings>,
+) {
+    let title = if result.success() && puzzle.is_some() {
+        "Puzzle solved!"
+    } else if result.success() {
+        "Course complete!"
+    } else {
+        "Not quite..."
+    };
+    let course = course.definition();
+    let course_goals = course.goals;
+    let stars = (0..course_goals.len())
+        .map(|i| if i < result.stars() { "*" } else { "-" })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let goals = course_goals
+        .iter()
+        .zip(&result.met)
+        .map(|(goal, met)| format!("[{}] {}", if *met { "x" } else { " " }, goal.description()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let bindings = &settings.key_bindings;
+    let mut hints = vec![
+        format!("{:?} - replay", bindings.replay),
+        "Enter - try again".to_string(),
+        "Esc - main menu".to_string(),
+    ];
+    if course.puzzle.is_some() {
+        hints.insert(2, format!("{:?} - puzzle", bindings.puzzle));
+    }
+
+    commands
+        .spawn((
+            CompletionScreen,
+            DespawnOnExit(GameState::Results),
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Px(60.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        row_gap: Val::Px(8.0),
+                        padding: UiRect::all(Val::Px(16.0)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+                ))
+                .with_children(|panel| {
+                    panel.spawn((Text::new(title), TextFont::from_font_size(32.0)));
+                    panel.spawn((
+                        Text::new(stars),
+                        TextFont::from_font_size(40.0),
+                        TextColor(css::GOLD.into()),
+                    ));
+                    panel.spawn(Text::new(goals));
+                    panel.spawn((Text::new(hints.join("   ")), TextFont::from_font_size(14.0)));
+                });
+        });
+}
+
+#[derive(TnuaScheme)]
+#[scheme(basis = TnuaBuiltinWalk)]
+enum ControlScheme {
+    Jump(TnuaBuiltinJump),
+    Dash(TnuaBuiltinDash),
+    Kick(MeleeAttack),
+    Punch(MeleeAttack),
+    Spin(MeleeAttack),
+    Crouch(Sneak, #[scheme(modify_basis_config)] SlowDownWhileCrouching),
+    Knockback(TnuaBuiltinKnockback),
+    Climb(TnuaBuiltinClimb),
+    WallSlide(TnuaBuiltinWallSlide),
+    WallJump(TnuaBuiltinJump),
+}
+
+/// Reads a [`ControlSchemeConfig`] from a `.control.ron` file, so movement can be tuned without
+/// rebuilding. With the `file_watcher` feature (on by default outside the browser) changes to the
+/// file apply while the game runs.
+#[derive(Default)]
+struct ControlSchemeConfigLoader;
+
+impl AssetLoader for ControlSchemeConfigLoader {
+    type Asset = ControlSchemeConfig;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<ControlSchemeConfig, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["control.ron"]
+    }
+}
+
+/// A melee attack [action](TnuaAction). Plays out for a fixed duration and strikes once, at
+/// [`hit_time`](MeleeAttackConfig::hit_time).
+#[derive(Default)]
+struct MeleeAttack;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct MeleeAttackConfig {
+    /// Total length of the attack, in seconds.
+    duration: f32,
+    /// Time into the attack, in seconds, when the strike lands.
+    hit_time: f32,
+    /// Reach of the strike, measured horizontally from the character.
+    range: f32,
+    /// Half-angle of the cone in front of the character that gets hit. `PI` hits all around.
+    half_angle: f32,
+    /// Impulse applied to a domino at point-blank range. Falls off linearly to zero at `range`.
+    impulse: f32,
+    /// Height above the character's feet where the impulse is applied.
+    height: f32,
+}
+
+#[derive(Default)]
+struct MeleeAttackMemory {
+    elapsed: f32,
+    /// Only `true` during the single frame in which the strike lands.
+    striking: bool,
+}
+
+impl<B: TnuaBasisWithGround> TnuaAction<B> for MeleeAttack {
+    type Config = MeleeAttackConfig;
+    type Memory = MeleeAttackMemory;
+
+    fn initiation_decision(
+        &self,
+        _config: &Self::Config,
+        _sensors: &B::Sensors<'_>,
+        ctx: TnuaActionContext<B>,
+        _being_fed_for: &Stopwatch,
+    ) -> TnuaActionInitiationDirective {
+        if B::is_airborne(ctx.basis) {
+            TnuaActionInitiationDirective::Reject
+        } else {
+            TnuaActionInitiationDirective::Allow
+        }
+    }
+
+    fn apply(
+        &self,
+        config: &Self::Config,
+        memory: &mut Self::Memory,
+        _sensors: &B::Sensors<'_>,
+        ctx: TnuaActionContext<B>,
+        lifecycle_status: TnuaActionLifecycleStatus,
+        _motor: &mut TnuaMotor,
+    ) -> TnuaActionLifecycleDirective {
+        let before = memory.elapsed;
+        memory.elapsed += ctx.frame_duration;
+        memory.striking = before < config.hit_time && config.hit_time <= memory.elapsed;
+
+        if config.duration <= memory.elapsed {
+            TnuaActionLifecycleDirective::Finished
+        } else {
+            lifecycle_status.directive_linger()
+        }
+    }
+}
+
+/// [`TnuaBuiltinCrouch`], with the speed the character sneaks at while crouched kept in the same
+/// config so it's tuned alongside the walk speed.
+#[derive(Default)]
+struct Sneak;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SneakConfig {
+    /// See [`TnuaBuiltinCrouchConfig::float_offset`].
+    float_offset: f32,
+    /// See [`TnuaBuiltinCrouchConfig::height_change_impulse_for_duration`].
+    height_change_impulse_for_duration: f32,
+    /// See [`TnuaBuiltinCrouchConfig::height_change_impulse_limit`].
+    height_change_impulse_limit: f32,
+    /// Top ground speed while crouched.
+    speed: f32,
+}
+
+impl SneakConfig {
+    fn crouch(&self) -> TnuaBuiltinCrouchConfig {
+        TnuaBuiltinCrouchConfig {
+            float_offset: self.float_offset,
+            height_change_impulse_for_duration: self.height_change_impulse_for_duration,
+            height_change_impulse_limit: self.height_change_impulse_limit,
+        }
+    }
+}
+
+impl<B> TnuaAction<B> for Sneak
+where
+    B: TnuaBasisWithFloating + TnuaBasisWithSpring + TnuaBasisWithGround + TnuaBasisWithHeadroom,
+{
+    type Config = SneakConfig;
+    type Memory = TnuaBuiltinCrouchMemory;
+
+    fn initiation_decision(
+        &self,
+        config: &Self::Config,
+        sensors: &B::Sensors<'_>,
+        ctx: TnuaActionContext<B>,
+        being_fed_for: &Stopwatch,
+    ) -> TnuaActionInitiationDirective {
+        TnuaBuiltinCrouch.initiation_decision(&config.crouch(), sensors, ctx, being_fed_for)
+    }
+
+    fn apply(
+        &self,
+        config: &Self::Config,
+        memory: &mut Self::Memory,
+        sensors: &B::Sensors<'_>,
+        ctx: TnuaActionContext<B>,
+        lifecycle_status: TnuaActionLifecycleStatus,
+        motor: &mut TnuaMotor,
+    ) -> TnuaActionLifecycleDirective {
+        TnuaBuiltinCrouch.apply(
+            &config.crouch(),
+            memory,
+            sensors,
+            ctx,
+            lifecycle_status,
+            motor,
+        )
+    }
+
+    fn influence_basis(
+        &self,
+        config: &Self::Config,
+        memory: &Self::Memory,
+        ctx: TnuaBasisContext,
+        basis_input: &B,
+        basis_config: &B::Config,
+        basis_memory: &mut B::Memory,
+    ) {
+        TnuaBuiltinCrouch.influence_basis(
+            &config.crouch(),
+            memory,
+            ctx,
+            basis_input,
+            basis_config,
+            basis_memory,
+        );
+    }
+}
+
+/// Caps the walk speed at the [sneak speed](SneakConfig::speed) while crouched.
+pub struct SlowDownWhileCrouching(pub f32);
+
+impl TnuaConfigModifier<TnuaBuiltinWalkConfig> for SlowDownWhileCrouching {
+    fn modify_config(&self, config: &mut TnuaBuiltinWalkConfig) {
+        config.speed = config.speed.min(self.0);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationState {
+    Standing,
+    /// Walking and running, blended by ground speed.
+    Running(f32),
+    WalkingBackwards(f32),
+    StrafingLeft(f32),
+    StrafingRight(f32),
+    Jumping,
+    Falling,
+    Landing,
+    Dashing(Heading),
+    Kicking,
+    Punching,
+    Spinning,
+    PickingUp,
+    SettingDown,
+    AdjustingGrip,
+    Aiming,
+    Throwing,
+    Shooting,
+    Crouching,
+    Sneaking(f32),
+    Ragdolling,
+    StandingUp,
+    Flinching,
+    Staggering,
+    EnteringEmote(Emote),
+    HoldingEmote(Emote),
+    ExitingEmote(Emote),
+    Hanging,
+    ClimbingUp,
+    WallSliding,
+}
+
+impl AnimationState {
+    /// The clip this state plays, with its playback speed and whether it loops.
+    fn clip(&self) -> (&'static str, f32, bool) {
+        match self {
+            AnimationState::Standing => ("Idle", 1.0, true),
+            // `Running_A` follows along under the same blend node.
+            AnimationState::Running(speed) => ("Walking_A", *speed / WALK_CLIP_SPEED, true),
+            AnimationState::WalkingBackwards(speed) => {
+                ("Walking_Backwards", *speed / WALK_CLIP_SPEED, true)
+            }
+            AnimationState::StrafingLeft(speed) => {
+                ("Running_Strafe_Left", *speed / STRAFE_CLIP_SPEED, true)
+            }
+            AnimationState::StrafingRight(speed) => {
+                ("Running_Strafe_Right", *speed / STRAFE_CLIP_SPEED, true)
+            }
+            AnimationState::Jumping => ("Jump_Start", 2.0, false),
+            AnimationState::Falling => ("Jump_Idle", 1.0, false),
+            AnimationState::Landing => ("Jump_Land", 1.0, false),
+            AnimationState::Dashing(heading) => {
+                let clip = match heading {
+                    Heading::Forward => "Dodge_Forward",
+                    Heading::Backward => "Dodge_Backward",
+                    Heading::Left => "Dodge_Left",
+                    Heading::Right => "Dodge_Right",
+                };
+                (clip, 1.0, false)
+            }
+            AnimationState::Kicking => ("Unarmed_Melee_Attack_Kick", 1.0, false),
+            AnimationState::Punching => ("Unarmed_Melee_Attack_Punch_A", 1.0, false),
+            AnimationState::Spinning => ("2H_Melee_Attack_Spin", 1.0, false),
+            AnimationState::PickingUp => ("PickUp", 1.5, false),
+            AnimationState::SettingDown => ("Interact", 1.5, false),
+            AnimationState::AdjustingGrip => ("Use_Item", 2.0, false),
+            AnimationState::Aiming => ("1H_Ranged_Aiming", 1.0, true),
+            AnimationState::Throwing => ("Throw", 1.5, false),
+            AnimationState::Shooting => ("1H_Ranged_Shoot", 1.5, false),
+            // The Knight has no dedicated crouch clips - these are its lowest stances.
+            AnimationState::Crouching => ("2H_Melee_Idle", 1.0, true),
+            AnimationState::Sneaking(speed) => ("Walking_C", *speed / SNEAK_CLIP_SPEED, true),
+            // Only the bones the ragdoll leaves alone (hands, feet) show this.
+            AnimationState::Ragdolling => ("Death_A", 1.0, false),
+            AnimationState::StandingUp => ("Lie_StandUp", 1.0, false),
+            AnimationState::Flinching => ("Hit_A", 1.5, false),
+            AnimationState::Staggering => ("Hit_B", 1.0, false),
+            AnimationState::EnteringEmote(emote) => (emote.clips().0.unwrap_or("Idle"), 1.0, false),
+            AnimationState::HoldingEmote(emote) => {
+                let (enter, hold, _) = emote.clips();
+                (hold, 1.0, enter.is_some())
+            }
+            AnimationState::ExitingEmote(emote) => (emote.clips().2.unwrap_or("Idle"), 1.0, false),
+            AnimationState::Hanging => ("Jump_Idle", 0.5, true),
+            AnimationState::ClimbingUp => ("Interact", 1.5, false),
+            AnimationState::WallSliding => ("Jump_Idle", 1.0, true),
+        }
+    }
+}
+
+/// States that only need the arms, and get layered over the legs' locomotion while moving.
+const UPPER_BODY_STATES: [AnimationState; 7] = [
+    AnimationState::Punching,
+    AnimationState::PickingUp,
+    AnimationState::SettingDown,
+    AnimationState::AdjustingGrip,
+    AnimationState::Aiming,
+    AnimationState::Throwing,
+    AnimationState::Shooting,
+];
+
+/// The bone everything above the hips hangs off.
+const UPPER_BODY_BONE: &str = "spine";
+const UPPER_BODY_MASK_GROUP: u32 = 0;
+const LOWER_BODY_MASK_GROUP: u32 = 1;
+const UPPER_BODY_FADE: f32 = 0.15;
+/// Clips blended together by ground speed while walking or running.
+const LOCOMOTION_CLIPS: [&str; 2] = ["Walking_A", "Running_A"];
+
+#[derive(Resource)]
+struct Animations {
     animations: HashMap<String, AnimationNodeIndex>,
+    /// Copies of the [`UPPER_BODY_STATES`] clips that leave the lower body alone.
+    upper_body: HashMap<String, AnimationNodeIndex>,
     graph_handle: Handle<AnimationGraph>,
 }
 
-fn setup_level(
+impl Animations {
+    fn get(&self, name: &str) -> Option<AnimationNodeIndex> {
+        self.animations.get(name).copied()
+    }
+
+    fn upper_body(&self, name: &str) -> Option<AnimationNodeIndex> {
+        self.upper_body.get(name).copied()
+    }
+
+    /// Whether the clip has played through. Clips that are missing or not playing count as
+    /// finished, so nothing waits on them forever.
+    fn is_finished(&self, animation_player: &AnimationPlayer, name: &str) -> bool {
+        self.get(name)
+            .and_then(|node| animation_player.animation(node))
+            .is_none_or(|animation| animation.is_finished())
+    }
+}
+
+/// The character glTF, kept around until its named clips are turned into [`Animations`].
+#[derive(Resource)]
+struct CharacterGltf(Handle<Gltf>);
+
+fn setup_level(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((Camera3d::default(), ThirdPersonCamera::default()));
+
+    commands.spawn((PointLight::default(), Transform::from_xyz(5.0, 5.0, 5.0)));
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: 4000.0,
+            shadows_enabled: true,
+            ..default()
+        },
+        Transform::default().looking_at(-Vec3::Y, Vec3::Z),
+    ));
+
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::default().mesh().size(512.0, 512.0))),
+        MeshMaterial3d(materials.add(Color::WHITE)),
+        RigidBody::Static,
+        Collider::half_space(Vec3::Y),
+    ));
+
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(10.0, 1.0, 10.0))),
+        MeshMaterial3d(materials.add(Color::from(css::GRAY))),
+        Transform::from_xyz(-6.0, 2.0, 0.0),
+        RigidBody::Static,
+        Collider::cuboid(10.0, 1.0, 10.0),
+    ));
+
+    // Somewhere to sit and watch the chain from.
+    let seat_material = materials.add(Color::from(css::SADDLE_BROWN));
+    for (transform, width) in [
+        (Transform::from_xyz(2.0, 0.0, 6.0), 2.0),
+        (
+            Transform::from_xyz(-0.5, 0.0, 5.0).with_rotation(Quat::from_rotation_y(0.4)),
+            0.7,
+        ),
+    ] {
+        commands
+            .spawn((Seat, transform, Visibility::default(), RigidBody::Static))
+            .with_children(|parent| {
+                parent.spawn((
+                    Mesh3d(meshes.add(Cuboid::new(width, SEAT_HEIGHT, SEAT_DEPTH))),
+                    MeshMaterial3d(seat_material.clone()),
+                    Transform::from_xyz(0.0, SEAT_HEIGHT / 2.0, 0.0),
+                    Collider::cuboid(width, SEAT_HEIGHT, SEAT_DEPTH),
+                ));
+                parent.spawn((
+                    Mesh3d(meshes.add(Cuboid::new(width, 0.8, 0.1))),
+                    MeshMaterial3d(seat_material.clone()),
+                    Transform::from_xyz(0.0, SEAT_HEIGHT + 0.4, SEAT_DEPTH / 2.0),
+                    Collider::cuboid(width, 0.8, 0.1),
+                ));
+            });
+    }
+}
+
+fn spawn_conveyor(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    conveyor: &Conveyor,
+) {
+    let size = conveyor.size;
+    let mut belt = commands.spawn((
+        ConveyorBelt {
+            direction: conveyor.direction,
+            speed: conveyor.speed,
+        },
+        DespawnOnExit(InCourse),
+        Mesh3d(meshes.add(Cuboid::from_size(size))),
+        Transform::from_translation(conveyor.center),
+        Collider::cuboid(size.x, size.y, size.z),
+        Friction::new(1.0),
+    ));
+    let Some(lift) = &conveyor.lift else {
+        belt.insert((
+            MeshMaterial3d(materials.add(Color::from(css::DIM_GRAY))),
+            RigidBody::Static,
+        ));
+        return;
+    };
+    let lift_material = materials.add(Color::from(css::GOLDENROD));
+    belt.insert((
+        MovingPlatform::new(lift.waypoints.to_vec(), lift.speed, lift.stop),
+        MeshMaterial3d(lift_material.clone()),
+        RigidBody::Kinematic,
+    ))
+    .with_children(|parent| {
+        // The belt keeps things away from the open end.
+        for side in [1.0, -1.0] {
+            let rail = Vec3::new(size.x, 0.2, 0.1);
+            parent.spawn((
+                Mesh3d(meshes.add(Cuboid::from_size(rail))),
+                MeshMaterial3d(lift_material.clone()),
+                Transform::from_xyz(0.0, size.y, side * (size.z - rail.z) / 2.0),
+                Collider::cuboid(rail.x, rail.y, rail.z),
+            ));
+        }
+    });
+}
+
+/// A kinematic body that travels through `waypoints` in a loop, stopping at each one for a
+/// while.
+#[derive(Component)]
+struct MovingPlatform {
+    waypoints: Vec<Vec3>,
+    speed: f32,
+    /// Index of the waypoint it is heading for.
+    next: usize,
+    /// Counts down the stop at the last waypoint reached.
+    stop: Timer,
+}
+
+impl MovingPlatform {
+    /// Starts out stopped at the first waypoint, where it should be spawned.
+    fn new(waypoints: Vec<Vec3>, speed: f32, stop: f32) -> Self {
+        Self {
+            waypoints,
+            speed,
+            next: 1,
+            stop: Timer::from_seconds(stop, TimerMode::Once),
+        }
+    }
+}
+
+/// Drives moving platforms by velocity, so that what stands on them (Tnua included) rides along.
+fn move_platforms(
+    time: Res<Time>,
+    mut platforms: Query<(&mut MovingPlatform, &Position, &mut LinearVelocity)>,
+) {
+    let dt = time.delta_secs();
+    for (mut platform, position, mut velocity) in &mut platforms {
+        if !platform.stop.tick(time.delta()).is_finished() || dt == 0.0 {
+            velocity.0 = Vec3::ZERO.adjust_precision();
+            continue;
+        }
+        let target = platform.waypoints[platform.next % platform.waypoints.len()];
+        let to_target = target - position.0.f32();
+        if to_target.length() <= platform.speed * dt {
+            // Land exactly on the waypoint this step, then wait there.
+            velocity.0 = (to_target / dt).adjust_precision();
+            platform.next = (platform.next + 1) % platform.waypoints.len();
+            platform.stop.reset();
+        } else {
+            velocity.0 = (to_target.normalize() * platform.speed).adjust_precision();
+        }
+    }
+}
+
+/// A surface that moves what touches it along `direction` (in its local space) without moving
+/// itself.
+#[derive(Component)]
+#[require(ActiveCollisionHooks::MODIFY_CONTACTS)]
+struct ConveyorBelt {
+    direction: Vec3,
+    speed: f32,
+}
+
+impl ConveyorBelt {
+    fn velocity(&self, transform: &GlobalTransform) -> Vec3 {
+        transform.rotation() * self.direction * self.speed
+    }
+}
+
+/// Gives contacts with a belt a tangent velocity, so friction drags bodies along it.
+#[derive(SystemParam)]
+struct ConveyorHooks<'w, 's> {
+    belts: Query<'w, 's, (&'static ConveyorBelt, &'static GlobalTransform)>,
+}
+
+impl CollisionHooks for ConveyorHooks<'_, '_> {
+    fn modify_contacts(&self, contacts: &mut ContactPair, _commands: &mut Commands) -> bool {
+        let (belt, sign) = match self.belts.get(contacts.collider1) {
+            Ok(belt) => (belt, -1.0),
+            Err(_) => match self.belts.get(contacts.collider2) {
+                Ok(belt) => (belt, 1.0),
+                Err(_) => return true,
+            },
+        };
+        let tangent_velocity = sign * belt.0.velocity(belt.1);
+        for manifold in contacts.manifolds.iter_mut() {
+            manifold.tangent_velocity = tangent_velocity.adjust_precision();
+        }
+        true
+    }
+}
+
+/// The character controller floats above the ground instead of resting on it, so friction alone
+/// won't carry it. Tell it the ground under a belt is moving instead.
+fn carry_on_conveyors(
+    mut sensors: Query<&mut TnuaProximitySensor>,
+    belts: Query<(&ConveyorBelt, &GlobalTransform)>,
+) {
+    for mut sensor in &mut sensors {
+        if let Some(output) = sensor.output.as_mut()
+            && let Ok((belt, transform)) = belts.get(output.entity)
+        {
+            output.entity_linvel += belt.velocity(transform).adjust_precision();
+        }
+    }
+}
+
+#[derive(Component)]
+struct Player;
+
+const PLAYER_HEIGHT: f32 = 2.0;
+const PLAYER_CROUCH_HEIGHT: f32 = 1.2;
+const PLAYER_RADIUS: f32 = 0.5;
+
+/// Everything that differs between the playable characters.
+struct CharacterDefinition {
+    name: &'static str,
+    model_path: &'static str,
+    scene_index: usize,
+    /// Replaces the base color texture of every material in the model, for alternate skins.
+    texture: Option<&'static str>,
+    /// Clips this model names differently, as `(name in ANIMATIONS, name in the model)`.
+    animation_names: &'static [(&'static str, &'static str)],
+    height: f32,
+    crouch_height: f32,
+    radius: f32,
+    /// Movement tuning, see [`ControlSchemeConfigLoader`].
+    control_scheme: &'static str,
+}
+
+/// Only models that ship in `assets/models/characters` belong here.
+const CHARACTERS: [CharacterDefinition; 1] = [CharacterDefinition {
+    name: "Knight",
+    model_path: "models/characters/Knight.glb",
+    scene_index: 0,
+    texture: None,
+    animation_names: &[],
+    height: PLAYER_HEIGHT,
+    crouch_height: PLAYER_CROUCH_HEIGHT,
+    radius: PLAYER_RADIUS,
+    control_scheme: "characters/knight.control.ron",
+}];
+
+impl CharacterDefinition {
+    fn collider(&self, crouching: bool) -> Collider {
+        let height = if crouching {
+            self.crouch_height
+        } else {
+            self.height
+        };
+        Collider::capsule_endpoints(
+            self.radius,
+            Vec3::Y * (height - self.radius),
+            Vec3::Y * self.radius,
+        )
+    }
+}
+
+/// The character picked on the selection screen, as an index into [`CHARACTERS`].
+#[derive(Resource)]
+struct SelectedCharacter(usize);
+
+impl SelectedCharacter {
+    fn definition(&self) -> &'static CharacterDefinition {
+        &CHARACTERS[self.0]
+    }
+}
+
+#[derive(Component)]
+struct CharacterSelect;
+
+fn spawn_character_select(commands: &mut Commands) {
+    let choices = CHARACTERS
+        .iter()
+        .enumerate()
+        .map(|(i, character)| format!("{} - {}", i + 1, character.name))
+        .collect::<Vec<_>>();
+    commands.spawn((
+        CharacterSelect,
+        DespawnOnExit(GameState::MainMenu),
+        Text::new(format!("Choose a character:\n{}", choices.join("\n"))),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
+    ));
+}
+
+fn choose_character(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    select: Query<Entity, With<CharacterSelect>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    const KEYS: [KeyCode; 4] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+    ];
+    let Some(index) = KEYS
+        .iter()
+        .take(CHARACTERS.len())
+        .position(|key| keyboard.just_pressed(*key))
+    else {
+        return;
+    };
+    info!("Playing as {}", CHARACTERS[index].name);
+    commands.insert_resource(SelectedCharacter(index));
+    for entity in &select {
+        commands.entity(entity).despawn();
+    }
+    next_state.set(GameState::Loading);
+}
+
+/// Present on the player while its collider is shrunk for crouching.
+#[derive(Component)]
+struct Crouched;
+
+#[allow(clippy::type_complexity)]
+fn sync_crouch_collider(
+    mut commands: Commands,
+    query: Query<(Entity, &TnuaController<ControlScheme>, Has<Crouched>), With<Player>>,
+    character: Res<SelectedCharacter>,
+) {
+    let Ok((player, controller, crouched)) = query.single() else {
+        return;
+    };
+    let crouching =
+        controller.action_discriminant() == Some(ControlSchemeActionDiscriminant::Crouch);
+    if crouching && !crouched {
+        commands
+            .entity(player)
+            .insert((Crouched, character.definition().collider(true)));
+    } else if !crouching && crouched {
+        commands
+            .entity(player)
+            .remove::<Crouched>()
+            .insert(character.definition().collider(false));
+    }
+}
+
+fn setup_player(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    character: Res<SelectedCharacter>,
+) {
+    let character = character.definition();
+    let model = asset_server.load(character.model_path);
+    let texture = character.texture.map(|texture| asset_server.load(texture));
+    let control_scheme = asset_server.load(character.control_scheme);
+    commands.insert_resource(CharacterGltf(model.clone()));
+    commands.insert_resource(CharacterAssets {
+        model,
+        texture: texture.clone(),
+        control_scheme: control_scheme.clone(),
+    });
+
+    commands
+        .spawn((
+            Player,
+            Transform::from_xyz(0.0, 2.0, 0.0),
+            TnuaAnimatingState::<AnimationState>::default(),
+            Hands::default(),
+            FallTracking::default(),
+            WallSense::default(),
+            CollisionEventsEnabled,
+            RigidBody::Dynamic,
+            character.collider(false),
+            TnuaController::<ControlScheme>::default(),
+            TnuaConfig::<ControlScheme>(control_scheme),
+            TnuaAvian3dSensorShape(Collider::cylinder(character.radius - 0.01, 0.0)),
+            LockedAxes::ROTATION_LOCKED.unlock_rotation_y(),
+        ))
+        .with_children(|parent| {
+            let mut scene = parent.spawn((
+                SceneRoot(asset_server.load(
+                    GltfAssetLabel::Scene(character.scene_index).from_asset(character.model_path),
+                )),
+                Transform::from_rotation(Quat::from_rotation_y(PI)),
+            ));
+            scene.observe(setup_foot_ik);
+            if let Some(texture) = texture {
+                scene.observe(
+                    move |scene_ready: On<SceneInstanceReady>,
+                          children: Query<&Children>,
+                          mut mesh_materials: Query<&mut MeshMaterial3d<StandardMaterial>>,
+                          mut materials: ResMut<Assets<StandardMaterial>>| {
+                        for child in children.iter_descendants(scene_ready.entity) {
+                            let Ok(mut mesh_material) = mesh_materials.get_mut(child) else {
+                                continue;
+                            };
+                            let Some(material) = materials.get(&mesh_material.0) else {
+                                continue;
+                            };
+                            let material = StandardMaterial {
+                                base_color_texture: Some(texture.clone()),
+                                ..material.clone()
+                            };
+                            mesh_material.0 = materials.add(material);
+                        }
+                    },
+                );
+            }
+        });
+}
+
+/// Builds the animation graph from the clips the glTF names, once it has loaded.
+fn build_animations(
+    mut commands: Commands,
+    character_gltf: Res<CharacterGltf>,
+    character: Res<SelectedCharacter>,
+    gltfs: Res<Assets<Gltf>>,
+    gltf_nodes: Res<Assets<GltfNode>>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+) {
+    let character = character.definition();
+    // A failed load is reported by `track_loading`.
+    let Some(gltf) = gltfs.get(&character_gltf.0) else {
+        return;
+    };
+
+    let mut graph = AnimationGraph::new();
+    // Walking and running share a blend node, weighed against each other by
+    // `follow_locomotion_weight`.
+    let locomotion = graph.add_blend(1.0, graph.root);
+    let mut names = gltf.named_animations.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut animations = names
+        .into_iter()
+        .map(|name| {
+            let clip = gltf.named_animations[name].clone();
+            let parent = if LOCOMOTION_CLIPS.contains(&&**name) {
+                locomotion
+            } else {
+                graph.root
+            };
+            (name.to_string(), graph.add_clip(clip, 1.0, parent))
+        })
+        .collect::<HashMap<_, _>>();
+    for (name, clip) in character.animation_names {
+        if let Some(node_index) = animations.get(*clip).copied() {
+            animations.insert(name.to_string(), node_index);
+        }
+    }
+
+    for root in &gltf.nodes {
+        let is_child = gltf
+            .nodes
+            .iter()
+            .filter_map(|node| gltf_nodes.get(node))
+            .any(|parent| parent.children.contains(root));
+        if let Some(root) = gltf_nodes.get(root).filter(|_| !is_child) {
+            assign_mask_groups(&mut graph, &gltf_nodes, root, &mut Vec::new(), false);
+        }
+    }
+    let upper_body = UPPER_BODY_STATES
+        .iter()
+        .filter_map(|state| {
+            let (name, ..) = state.clip();
+            let clip = character
+                .animation_names
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map_or(name, |(_, clip)| *clip);
+            let clip = gltf.named_animations.get(clip)?.clone();
+            let node_index =
+                graph.add_clip_with_mask(clip, 1 << LOWER_BODY_MASK_GROUP, 1.0, graph.root);
+            Some((name.to_string(), node_index))
+        })
+        .collect();
+
+    let missing = ANIMATIONS
+        .into_iter()
+        .filter(|name| !animations.contains_key(*name))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        warn!(
+            "{} is missing animations: {}",
+            character.model_path,
+            missing.join(", ")
+        );
+    }
+
+    commands.insert_resource(Animations {
+        animations,
+        upper_body,
+        graph_handle: graphs.add(graph),
+    });
+    commands.remove_resource::<CharacterGltf>();
+}
+
+/// Splits the skeleton into the upper body ([`UPPER_BODY_BONE`] and below) and the rest.
+fn assign_mask_groups(
+    graph: &mut AnimationGraph,
+    gltf_nodes: &Assets<GltfNode>,
+    node: &GltfNode,
+    path: &mut Vec<Name>,
+    upper_body: bool,
+) {
+    let upper_body = upper_body || node.name == UPPER_BODY_BONE;
+    path.push(Name::new(node.name.clone()));
+    graph.add_target_to_mask_group(
+        AnimationTargetId::from_names(path.iter()),
+        if upper_body {
+            UPPER_BODY_MASK_GROUP
+        } else {
+            LOWER_BODY_MASK_GROUP
+        },
+    );
+    for child in node
+        .children
+        .iter()
+        .filter_map(|child| gltf_nodes.get(child))
+    {
+        assign_mask_groups(graph, gltf_nodes, child, path, upper_body);
+    }
+    path.pop();
+}
+
+/// Hooks the animation graph up to the character's player, whichever of the two is ready last.
+fn attach_animation_graph(
+    mut commands: Commands,
+    players: Query<Entity, (With<AnimationPlayer>, Without<AnimationGraphHandle>)>,
+    animations: Res<Animations>,
+) {
+    for entity in &players {
+        commands.entity(entity).insert((
+            AnimationGraphHandle(animations.graph_handle.clone()),
+            AnimationTransitions::new(),
+            UpperBodyLayer::default(),
+            LocomotionBlend::default(),
+        ));
+    }
+}
+
+/// How far in front of the character's chest to look for a wall.
+const WALL_REACH: f32 = 0.4;
+/// Ledges between these heights above the feet can be grabbed from the air.
+const LEDGE_GRAB_MIN_HEIGHT: f32 = 1.2;
+const LEDGE_GRAB_MAX_HEIGHT: f32 = 2.6;
+/// Walls steeper than this (the vertical part of their normal) can be slid down and grabbed.
+const MAX_WALL_NORMAL_Y: f32 = 0.3;
+
+/// A static wall in front of the player, as seen by [`sense_walls`].
+#[derive(Clone, Copy)]
+struct WallContact {
+    point: Vec3,
+    normal: Dir3,
+    /// Where the top of the wall meets its face, if the player could reach up to it.
+    ledge: Option<Vec3>,
+    /// How far from the wall the character's center keeps while hanging on it.
+    clearance: f32,
+}
+
+#[derive(Component, Default)]
+struct WallSense(Option<WallContact>);
+
+/// Looks for a wall in front of the player, and for a ledge on top of it to grab.
+fn sense_walls(
+    spatial_query: SpatialQuery,
+    character: Option<Res<SelectedCharacter>>,
+    mut player_query: Query<(Entity, &Transform, &mut WallSense), With<Player>>,
+    collider_of: Query<&ColliderOf>,
+    bodies: Query<&RigidBody>,
+) {
+    let (Some(character), Ok((player, transform, mut sense))) =
+        (character, player_query.single_mut())
+    else {
+        return;
+    };
+    let character = character.definition();
+    sense.0 = None;
+
+    let Ok(forward) = Dir3::new(transform.forward().with_y(0.0)) else {
+        return;
+    };
+    let filter = solid_filter([player]);
+    let chest = transform.translation + Vec3::Y * character.height * 0.5;
+    let Some(hit) = spatial_query.cast_shape(
+        &Collider::sphere(character.radius * 0.9),
+        chest.adjust_precision(),
+        Quat::IDENTITY.adjust_precision(),
+        forward,
+        &ShapeCastConfig::from_max_distance(WALL_REACH.adjust_precision()),
+        &filter,
+    ) else {
+        return;
+    };
+    // Only level geometry - dominoes and balls would just get knocked over.
+    let body = collider_of.get(hit.entity).map_or(hit.entity, |of| of.body);
+    let normal = hit.normal1.f32();
+    if !bodies.get(body).is_ok_and(RigidBody::is_static) || MAX_WALL_NORMAL_Y < normal.y.abs() {
+        return;
+    }
+    let Ok(normal) = Dir3::new(normal.with_y(0.0)) else {
+        return;
+    };
+    let point = hit.point1.f32();
+
+    // Drop a ray just behind the wall's face to find its top.
+    let above = (point - *normal * 0.15).with_y(transform.translation.y + LEDGE_GRAB_MAX_HEIGHT);
+    let ledge = spatial_query
+        .cast_ray(
+            above.adjust_precision(),
+            Dir3::NEG_Y,
+            (LEDGE_GRAB_MAX_HEIGHT - LEDGE_GRAB_MIN_HEIGHT).adjust_precision(),
+            true,
+            &filter,
+        )
+        // Starting inside it means the wall is too tall to reach the top of.
+        .filter(|top| 0.0 < top.distance && 0.7 < top.normal.f32().y)
+        .map(|top| point.with_y(above.y - top.distance.f32()));
+
+    sense.0 = Some(WallContact {
+        point,
+        normal,
+        ledge,
+        clearance: character.radius + 0.05,
+    });
+}
+
+/// Ledge grabs, climbing up, wall slides and wall jumps. Returns whether the character is
+/// hanging on a ledge, in which case it takes no other input.
+fn apply_traversal(
+    controller: &mut TnuaController<ControlScheme>,
+    transform: &Transform,
+    wall: &WallSense,
+    direction: Vec3,
+    jump: bool,
+    falling: bool,
+    crouch: bool,
+) -> bool {
+    let airborne = controller.basis_memory.standing_on_entity().is_none();
+    let climbing = match controller.current_action.as_ref() {
+        Some(ControlSchemeActionState::Climb(state)) => Some(state.input.clone()),
+        _ => None,
+    };
+    let ledge = wall.0.and_then(|wall| Some((wall, wall.ledge?)));
+    let grab = ledge.filter(|(wall, _)| airborne && 0.5 < direction.dot(-*wall.normal));
+
+    if let Some(climb) = climbing.or_else(|| {
+        grab.map(|(wall, edge)| TnuaBuiltinClimb {
+            anchor: edge,
+            desired_vec_to_anchor: -*wall.normal * wall.clearance,
+            ..default()
+        })
+    }) {
+        let normal = -climb.desired_vec_to_anchor.normalize_or_zero();
+        let letting_go = crouch || 0.5 < direction.dot(normal);
+        let over_ledge = climb.anchor.y <= transform.translation.y;
+        // Once up, step in over the edge so there is ground to stand on when letting go.
+        let desired_vec_to_anchor = if over_ledge {
+            normal * climb.desired_vec_to_anchor.length()
+        } else {
+            climb.desired_vec_to_anchor
+        };
+        let stepped_in = over_ledge
+            && 0.8 * climb.desired_vec_to_anchor.length()
+                < (climb.anchor - transform.translation).dot(normal);
+        if letting_go || stepped_in {
+            return false;
+        }
+        let climbing_up = jump || climb.desired_climb_motion != Vec3::ZERO;
+        controller.action(ControlScheme::Climb(TnuaBuiltinClimb {
+            anchor: climb.anchor,
+            desired_vec_to_anchor,
+            desired_climb_motion: if climbing_up { Vec3::Y } else { Vec3::ZERO },
+            desired_forward: Dir3::new(-normal).ok(),
+            hard_stop_up: Some(climb.anchor + Vec3::Y * 0.1),
+            hard_stop_down: None,
+        }));
+        controller.basis = TnuaBuiltinWalk {
+            desired_motion: Vec3::ZERO,
+            desired_forward: None,
+        };
+        return true;
+    }
+
+    let sliding =
+        controller.action_discriminant() == Some(ControlSchemeActionDiscriminant::WallSlide);
+    if sliding && jump {
+        let normal = match controller.current_action.as_ref() {
+            Some(ControlSchemeActionState::WallSlide(state)) => state.input.normal,
+            _ => Dir3::Y,
+        };
+        controller.action(ControlScheme::WallJump(TnuaBuiltinJump {
+            horizontal_displacement: Some(*normal),
+            allow_in_air: true,
+            force_forward: Some(normal),
+        }));
+    } else if let Some(wall) = wall.0
+        && airborne
+        && (sliding || falling)
+        && 0.5 < direction.dot(-*wall.normal)
+    {
+        controller.action(ControlScheme::WallSlide(TnuaBuiltinWallSlide {
+            contact_point_with_wall: wall.point,
+            normal: wall.normal,
+            force_forward: Some(-wall.normal),
+        }));
+    }
+    false
+}
+
+#[allow(clippy::type_complexity)]
+fn apply_controls(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    camera: Single<&Transform, With<Camera3d>>,
+    mut query: Query<(
+        &mut TnuaController<ControlScheme>,
+        &TnuaConfig<ControlScheme>,
+        &Transform,
+        &LinearVelocity,
+        &Hands,
+        &WallSense,
+        Has<Knockdown>,
+        Has<Staggered>,
+        Has<Emoting>,
+    )>,
+    control_schemes: Res<Assets<ControlSchemeConfig>>,
+    build: Option<Res<BuildPhase>>,
+    state: Res<State<GameState>>,
+    settings: Res<Settings>,
+) {
+    let Ok((
+        mut controller,
+        control_scheme,
+        transform,
+        velocity,
+        hands,
+        wall,
+        knocked_down,
+        staggered,
+        emoting,
+    )) = query.single_mut()
+    else {
+        return;
+    };
+    controller.initiate_action_feeding();
+
+    let bindings = &settings.key_bindings;
+    let playing = *state.get() == GameState::Playing;
+    if knocked_down || staggered || emoting || build.is_some() || !playing {
+        controller.basis = TnuaBuiltinWalk {
+            desired_motion: Vec3::ZERO,
+            desired_forward: None,
+        };
+        return;
+    }
+
+    let mut direction = Vec3::ZERO;
+    if keyboard.pressed(bindings.forward) {
+        direction.z += 1.0;
+    }
+    if keyboard.pressed(bindings.back) {
+        direction.z -= 1.0;
+    }
+    if keyboard.pressed(bindings.left) {
+        direction.x -= 1.0;
+    }
+    if keyboard.pressed(bindings.right) {
+        direction.x += 1.0;
+    }
+
+    if direction != Vec3::ZERO {
+        let forward = *camera.forward();
+        let forward = Vec3::new(forward.x, 0.0, forward.z).normalize_or_zero();
+        let right = *camera.right();
+        let right = Vec3::new(right.x, 0.0, right.z).normalize_or_zero();
+        direction = (forward * direction.z + right * direction.x).normalize_or_zero();
+    }
+
+    let jump = keyboard.pressed(bindings.jump);
+    let falling = velocity.y <= 0.0;
+    if apply_traversal(
+        &mut controller,
+        transform,
+        wall,
+        direction,
+        jump,
+        falling,
+        keyboard.pressed(bindings.crouch),
+    ) {
+        return;
+    }
+
+    if jump {
+        controller.action(ControlScheme::Jump(TnuaBuiltinJump {
+            allow_in_air: false,
+            ..default()
+        }));
+    }
+
+    let dash = keyboard.pressed(bindings.dash);
+    if dash {
+        let up_direction = controller.up_direction().unwrap_or(Dir3::Y);
+        // While aiming, keep facing the target and dodge relative to it.
+        let desired_forward = if hands.aiming {
+            Dir3::new(camera.forward().with_y(0.0)).ok()
+        } else {
+            Dir3::new(direction.f32()).ok()
+        };
+        controller.action(ControlScheme::Dash(TnuaBuiltinDash {
+            displacement: direction.normalize() + up_direction.adjust_precision(),
+            desired_forward,
+            allow_in_air: true,
+        }));
+    }
+
+    if keyboard.pressed(bindings.crouch)
+        && let Some(config) = control_schemes.get(&control_scheme.0)
+    {
+        controller.action(ControlScheme::Crouch(
+            Sneak,
+            SlowDownWhileCrouching(config.crouch.speed),
+        ));
+    }
+
+    if keyboard.pressed(bindings.punch) {
+        controller.action(ControlScheme::Punch(MeleeAttack));
+    }
+    if keyboard.pressed(bindings.kick) {
+        controller.action(ControlScheme::Kick(MeleeAttack));
+    }
+    if keyboard.pressed(bindings.spin) {
+        controller.action(ControlScheme::Spin(MeleeAttack));
+    }
+
+    // Kicks and spins root the character in place until they play out. Punches only take the
+    // arms, so the legs keep going.
+    let attacking = matches!(
+        controller.action_discriminant(),
+        Some(ControlSchemeActionDiscriminant::Kick | ControlSchemeActionDiscriminant::Spin)
+    );
+
+    let turn_in_place = keyboard.pressed(bindings.turn_in_place);
+
+    controller.basis = TnuaBuiltinWalk {
+        desired_motion: if turn_in_place || attacking {
+            Vec3::ZERO
+        } else {
+            direction
+        },
+        desired_forward: if attacking {
+            None
+        } else if hands.aiming {
+            // Face where the camera looks so the throw goes where the preview shows.
+            Dir3::new(camera.forward().with_y(0.0)).ok()
+        } else {
+            Dir3::new(direction).ok()
+        },
+    };
+}
+
+fn apply_melee_strikes(
+    player_query: Query<(&TnuaController<ControlScheme>, &Transform), With<Player>>,
+    mut dominoes: Query<(Forces, &Transform), With<Domino>>,
+) {
+    let Ok((controller, player_transform)) = player_query.single() else {
+        return;
+    };
+    let state = match controller.current_action.as_ref() {
+        Some(ControlSchemeActionState::Kick(state))
+        | Some(ControlSchemeActionState::Punch(state))
+        | Some(ControlSchemeActionState::Spin(state)) => state,
+        _ => return,
+    };
+    if !state.memory.striking {
+        return;
+    }
+    let config = &state.config;
+
+    let origin = player_transform.translation;
+    let forward = player_transform.forward().with_y(0.0).normalize_or_zero();
+
+    for (mut forces, domino_transform) in &mut dominoes {
+        let offset = (domino_transform.translation - origin).with_y(0.0);
+        let distance = offset.length();
+        if config.range < distance {
+            continue;
+        }
+        let direction = offset.normalize_or(forward);
+        if config.half_angle < forward.angle_between(direction) {
+            continue;
+        }
+
+        // Cone attacks drive everything along the swing, all-around attacks push outward.
+        let push = if config.half_angle < FRAC_PI_2 {
+            forward
+        } else {
+            direction
+        };
+        let impulse = push * config.impulse * (1.0 - distance / config.range);
+        let point = domino_transform
+            .translation
+            .with_y(origin.y + config.height);
+        forces.apply_linear_impulse_at_point(impulse.adjust_precision(), point.adjust_precision());
+    }
+}
+
+/// How far in front of the player a domino can be picked up from or set down at.
+const HAND_REACH: f32 = 1.2;
+
+/// Placement orientations snap to multiples of this angle.
+const PLACEMENT_SNAP: f32 = PI / 12.0;
+
+const GESTURE_DURATION: f32 = 0.6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Gesture {
+    PickUp,
+    SetDown,
+    Adjust,
+    Throw,
+    Shoot,
+}
+
+/// The domino the player is carrying, and the hand gesture currently playing.
+#[derive(Component, Default)]
+struct Hands {
+    held: Option<Entity>,
+    /// Rotation of the held domino relative to the player, in `PLACEMENT_SNAP` steps.
+    turns: i32,
+    gesture: Option<(Gesture, Timer)>,
+    /// Whether the player is aiming a throw.
+    aiming: bool,
+    /// While the throw button is held, how far the throw is charged, from 0 to 1.
+    charge: Option<f32>,
+}
+
+impl Hands {
+    fn gesture(&mut self, gesture: Gesture) {
+        self.gesture = Some((
+            gesture,
+            Timer::from_seconds(GESTURE_DURATION, TimerMode::Once),
+        ));
+    }
+
+    /// The upright rotation the held domino would be set down with, with its thin side facing
+    /// the player so a push sends it away.
+    fn placement_rotation(&self, player_transform: &Transform) -> Quat {
+        let forward = player_transform.forward();
+        let yaw = f32::atan2(-forward.x, -forward.z) + FRAC_PI_2;
+        let snapped = (yaw / PLACEMENT_SNAP).round() as i32 + self.turns;
+        Quat::from_rotation_y(snapped as f32 * PLACEMENT_SNAP)
+    }
+}
+
+/// A domino currently carried by the player. Its body and collider are disabled meanwhile.
+#[derive(Component)]
+struct Held;
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn handle_hands(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    mut player_query: Query<
+        (Entity, &Transform, &mut Hands),
+        (With<Player>, Without<Knockdown>, Without<Emoting>),
+    >,
+    dominoes: Query<(Entity, &Transform, &LinearVelocity), (With<Domino>, Without<Ball>)>,
+    settings: Res<Settings>,
+    mut stopped_emoting: RemovedComponents<Emoting>,
+) {
+    let stopped_emoting = stopped_emoting.read().collect::<Vec<_>>();
+    let Ok((player, player_transform, mut hands)) = player_query.single_mut() else {
+        return;
+    };
+    // Sitting down skips this system while `Emoting`; the press that gets the player back up is
+    // spent on that too.
+    let interact =
+        keyboard.just_pressed(settings.key_bindings.interact) && !stopped_emoting.contains(&player);
+
+    if let Some((_, timer)) = hands.gesture.as_mut()
+        && timer.tick(time.delta()).is_finished()
+    {
+        hands.gesture = None;
+    }
+
+    let forward = player_transform.forward().with_y(0.0).normalize_or_zero();
+
+    if let Some(held) = hands.held {
+        if keyboard.just_pressed(settings.key_bindings.rotate_left) {
+            hands.turns -= 1;
+            hands.gesture(Gesture::Adjust);
+        }
+        if keyboard.just_pressed(settings.key_bindings.rotate_right) {
+            hands.turns += 1;
+            hands.gesture(Gesture::Adjust);
+        }
+        if !interact {
+            return;
+        }
+
+        // Find the surface under the spot in front of the player and stand the domino on it.
+        let filter = solid_filter([player, held]);
+        let above = player_transform.translation + forward * HAND_REACH + Vec3::Y * 3.0;
+        let Some(hit) =
+            spatial_query.cast_ray(above.adjust_precision(), Dir3::NEG_Y, 6.0, true, &filter)
+        else {
+            return;
+        };
+        let rotation = hands.placement_rotation(player_transform);
+        let position = above - Vec3::Y * (hit.distance.f32() - 0.5);
+
+        // Refuse to set it down inside something else.
+        let blocked = !spatial_query
+            .shape_intersections(
+                &Collider::cuboid(0.09, 0.9, 0.45),
+                (position + Vec3::Y * 0.05).adjust_precision(),
+                rotation.adjust_precision(),
+                &filter,
+            )
+            .is_empty();
+        if blocked {
+            return;
+        }
+
+        commands
+            .entity(held)
+            .remove::<(Held, RigidBodyDisabled, ColliderDisabled)>()
+            .insert((
+                Transform::from_translation(position).with_rotation(rotation),
+                LinearVelocity::ZERO,
+                AngularVelocity::ZERO,
+            ));
+        hands.held = None;
+        hands.gesture(Gesture::SetDown);
+    } else if interact {
+        let reach_center = player_transform.translation + forward * HAND_REACH * 0.5;
+        let nearest = dominoes
+            .iter()
+            .filter(|(_, transform, velocity)| {
+                transform.up().dot(Vec3::Y) > 0.95 && velocity.length() < 0.05
+            })
+            .map(|(entity, transform, _)| {
+                let distance = (transform.translation - reach_center).with_y(0.0).length();
+                (entity, distance)
+            })
+            .filter(|(_, distance)| *distance < HAND_REACH)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        if let Some((entity, _)) = nearest {
+            commands
+                .entity(entity)
+                .insert((Held, RigidBodyDisabled, ColliderDisabled));
+            hands.held = Some(entity);
+            hands.turns = 0;
+            hands.gesture(Gesture::PickUp);
+        }
+    }
+}
+
+fn carry_held_domino(
+    player_query: Query<(&Transform, &Hands), With<Player>>,
+    mut held_query: Query<&mut Transform, (With<Held>, Without<Player>)>,
+) {
+    let Ok((player_transform, hands)) = player_query.single() else {
+        return;
+    };
+    let Some(mut held_transform) = hands.held.and_then(|held| held_query.get_mut(held).ok()) else {
+        return;
+    };
+    let forward = player_transform.forward().with_y(0.0).normalize_or_zero();
+    held_transform.translation = player_transform.translation + forward * 0.7 + Vec3::Y * 1.2;
+    held_transform.rotation = hands.placement_rotation(player_transform);
+}
+
+const MIN_THROW_SPEED: f32 = 4.0;
+const MAX_THROW_SPEED: f32 = 16.0;
+
+/// Seconds of holding the throw button to reach full charge.
+const THROW_CHARGE_TIME: f32 = 1.0;
+
+/// Below this charge the ball is flicked rather than thrown overhand.
+const FULL_THROW_CHARGE: f32 = 0.5;
+
+/// Oldest thrown balls are removed once there are more than this many.
+const MAX_THROWN_BALLS: usize = 8;
+
+const THROWN_BALL_RADIUS: f32 = 0.25;
+
+#[derive(Component)]
+struct ThrownBall {
+    thrown_at: f32,
+}
+
+#[derive(Resource)]
+struct ThrownBallAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for ThrownBallAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            mesh: world.add_asset(Sphere::new(THROWN_BALL_RADIUS)),
+            material: world.add_asset(StandardMaterial::from(Color::from(css::GOLD))),
+        }
+    }
+}
+
+/// Where a thrown ball leaves the player's hand, and its initial velocity.
+fn throw_launch(
+    player_transform: &Transform,
+    camera: &ThirdPersonCamera,
+    charge: f32,
+) -> (Vec3, Vec3) {
+    let forward = player_transform.forward().with_y(0.0).normalize_or_zero();
+    // Tilting the camera up raises the arc.
+    let elevation = (0.6 + camera.pitch).clamp(-0.2, 1.0);
+    let direction = forward * elevation.cos() + Vec3::Y * elevation.sin();
+    let speed = MIN_THROW_SPEED.lerp(MAX_THROW_SPEED, charge);
+    let origin = player_transform.translation + Vec3::Y * 1.5 + forward * 0.6;
+    (origin, direction * speed)
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn handle_aiming(
+    mut commands: Commands,
+    mouse: Res<ButtonInput<MouseButton>>,
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    spatial_query: SpatialQuery,
+    ball_assets: Res<ThrownBallAssets>,
+    camera: Single<&ThirdPersonCamera>,
+    mut player_query: Query<
+        (Entity, &Transform, &mut Hands),
+        (With<Player>, Without<Knockdown>, Without<Emoting>),
+    >,
+    thrown_balls: Query<(Entity, &ThrownBall)>,
+    mut gizmos: Gizmos,
+) {
+    let Ok((player, player_transform, mut hands)) = player_query.single_mut() else {
+        return;
+    };
+
+    hands.aiming = hands.held.is_none() && mouse.pressed(MouseButton::Right);
+    if !hands.aiming {
+        hands.charge = None;
+        return;
+    }
+
+    if mouse.pressed(MouseButton::Left) {
+        let charge = hands.charge.unwrap_or(0.0) + time.delta_secs() / THROW_CHARGE_TIME;
+        hands.charge = Some(charge.min(1.0));
+    }
+
+    let charge = hands.charge.unwrap_or(0.0);
+    let (origin, velocity) = throw_launch(player_transform, &camera, charge);
+
+    if mouse.just_released(MouseButton::Left) {
+        hands.charge = None;
+        hands.gesture(if charge < FULL_THROW_CHARGE {
+            Gesture::Shoot
+        } else {
+            Gesture::Throw
+        });
+        commands.spawn((
+            Domino,
+            Ball,
+            ThrownBall {
+                thrown_at: time.elapsed_secs(),
+            },
+            DespawnOnExit(InCourse),
+            Mesh3d(ball_assets.mesh.clone()),
+            MeshMaterial3d(ball_assets.material.clone()),
+            Transform::from_translation(origin),
+            RigidBody::Dynamic,
+            Collider::sphere(THROWN_BALL_RADIUS),
+            Mass(1.0),
+            Restitution::new(0.5),
+            LinearVelocity(velocity.adjust_precision()),
+        ));
+
+        if MAX_THROWN_BALLS <= thrown_balls.iter().len()
+            && let Some((oldest, _)) = thrown_balls
+                .iter()
+                .min_by(|(_, a), (_, b)| a.thrown_at.total_cmp(&b.thrown_at))
+        {
+            commands.entity(oldest).despawn();
+        }
+        return;
+    }
+
+    // Preview the arc, stopping where it first meets something.
+    let filter = solid_filter([player]);
+    let gravity = gravity.0.f32();
+    let step = 1.0 / 30.0;
+    let color = Color::from(css::GOLD).mix(&Color::from(css::RED), charge);
+    let mut previous = origin;
+    for i in 1..90 {
+        let t = i as f32 * step;
+        let point = origin + velocity * t + 0.5 * gravity * t * t;
+        let segment = point - previous;
+        if let Ok(direction) = Dir3::new(segment)
+            && let Some(hit) = spatial_query.cast_ray(
+                previous.adjust_precision(),
+                direction,
+                segment.length().adjust_precision(),
+                true,
+                &filter,
+            )
+        {
+            let impact = previous + direction * hit.distance.f32();
+            gizmos.line(previous, impact, color);
+            gizmos.sphere(
+                Isometry3d::from_translation(impact),
+                THROWN_BALL_RADIUS,
+                color,
+            );
+            break;
+        }
+        gizmos.line(previous, point, color);
+        previous = point;
+    }
+}
+
+/// Ground speed at which `Walking_A` plays at its natural rate.
+const WALK_CLIP_SPEED: f32 = 3.0;
+/// Ground speed at which `Running_A` plays at its natural rate. Between the two, both clips are
+/// blended by speed.
+const RUN_CLIP_SPEED: f32 = 10.0;
+/// Ground speed at which the strafe clips play at their natural rate.
+const STRAFE_CLIP_SPEED: f32 = 6.0;
+/// Ground speed at which `Walking_C` plays at its natural rate.
+const SNEAK_CLIP_SPEED: f32 = 5.0;
+
+/// Weight of `Running_A` against `Walking_A` at the given ground speed.
+fn run_blend(speed: f32) -> f32 {
+    ((speed - WALK_CLIP_SPEED) / (RUN_CLIP_SPEED - WALK_CLIP_SPEED)).clamp(0.0, 1.0)
+}
+
+/// Where a motion points relative to the direction the character faces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Heading {
+    Forward,
+    Backward,
+    Left,
+    Right,
+}
+
+impl Heading {
+    fn of(direction: Vec3, facing: Vec3) -> Self {
+        let direction = direction.with_y(0.0).normalize_or_zero();
+        let facing = facing.with_y(0.0).normalize_or_zero();
+        let forward = direction.dot(facing);
+        let right = direction.dot(facing.cross(Vec3::Y));
+        if right.abs() <= forward.abs() {
+            if 0.0 <= forward {
+                Heading::Forward
+            } else {
+                Heading::Backward
+            }
+        } else if 0.0 < right {
+            Heading::Right
+        } else {
+            Heading::Left
+        }
+    }
+}
+
+/// How long to crossfade from one animation state into the next.
+fn crossfade_duration(from: Option<&AnimationState>, to: &AnimationState) -> Duration {
+    use AnimationState::*;
+    let seconds = match (from, to) {
+        (None, _) => 0.0,
+        // Snappy reactions to sudden changes in motion.
+        (Some(Falling), Landing) => 0.05,
+        (Some(_), Jumping | Dashing(_)) => 0.05,
+        (Some(_), Kicking | Punching | Spinning) => 0.1,
+        (Some(_), Ragdolling | Flinching | Staggering) => 0.05,
+        // Slower settling when coming to rest or shifting gait.
+        (Some(Running(_)), Standing) => 0.35,
+        (Some(Landing), _) => 0.25,
+        (Some(Crouching | Sneaking(_)), _) | (Some(_), Crouching | Sneaking(_)) => 0.25,
+        _ => 0.15,
+    };
+    Duration::from_secs_f32(seconds)
+}
+
+/// An arms-only clip played over whatever the legs are doing, fading in and out on its own.
+#[derive(Component, Default)]
+struct UpperBodyLayer {
+    state: Option<AnimationState>,
+    node: Option<AnimationNodeIndex>,
+    weight: f32,
+}
+
+impl UpperBodyLayer {
+    fn update(
+        &mut self,
+        animation_player: &mut AnimationPlayer,
+        animations: &Animations,
+        state: Option<AnimationState>,
+        delta: f32,
+    ) {
+        if let Some(new_state) = state.filter(|state| Some(*state) != self.state) {
+            if let Some(old_node) = self.node.take() {
+                animation_player.stop(old_node);
+            }
+            let (clip, speed, repeat) = new_state.clip();
+            self.node = animations.upper_body(clip);
+            if let Some(node) = self.node {
+                let animation = animation_player.start(node);
+                animation.replay();
+                animation.set_speed(speed);
+                if repeat {
+                    animation.repeat();
+                }
+            }
+        }
+        self.state = state;
+
+        let target = if state.is_some() { 1.0 } else { 0.0 };
+        let step = delta / UPPER_BODY_FADE;
+        self.weight = (self.weight + (target - self.weight).clamp(-step, step)).clamp(0.0, 1.0);
+
+        let Some(node) = self.node else {
+            return;
+        };
+        if self.weight == 0.0 {
+            animation_player.stop(node);
+            self.node = None;
+            return;
+        }
+        // Active clips are averaged by weight, and `AnimationTransitions` keeps the full-body
+        // ones summing to 1 - so this is what gives the layer a `self.weight` share of the arms.
+        let weight = self.weight.min(0.999);
+        if let Some(animation) = animation_player.animation_mut(node) {
+            animation.set_weight(weight / (1.0 - weight));
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn handle_animating(
+    mut player_query: Query<(
+        &TnuaController<ControlScheme>,
+        &Transform,
+        &Hands,
+        Option<&Knockdown>,
+        Option<&Staggered>,
+        Option<&Emoting>,
+        &mut TnuaAnimatingState<AnimationState>,
+    )>,
+    mut animation_player_query: Query<(
+        &mut AnimationPlayer,
+        &mut AnimationTransitions,
+        &mut UpperBodyLayer,
+        &mut LocomotionBlend,
+    )>,
+    animation_nodes: Option<Res<Animations>>,
+    time: Res<Time>,
+) {
+    let Ok((controller, transform, hands, knockdown, staggered, emoting, mut animating_state)) =
+        player_query.single_mut()
+    else {
+        return;
+    };
+    let Ok((mut animation_player, mut transitions, mut upper_body_layer, mut locomotion_blend)) =
+        animation_player_query.single_mut()
+    else {
+        return;
+    };
+    let Some(animation_nodes) = animation_nodes else {
+        return;
+    };
+
+    let facing = *transform.forward();
+    let velocity = controller.basis_memory.running_velocity;
+    let speed = velocity.length();
+
+    let upper_body_state = if matches!(
+        controller.current_action,
+        Some(ControlSchemeActionState::Punch(_))
+    ) {
+        Some(AnimationState::Punching)
+    } else if let Some((gesture, _)) = &hands.gesture {
+        Some(match gesture {
+            Gesture::PickUp => AnimationState::PickingUp,
+            Gesture::SetDown => AnimationState::SettingDown,
+            Gesture::Adjust => AnimationState::AdjustingGrip,
+            Gesture::Throw => AnimationState::Throwing,
+            Gesture::Shoot => AnimationState::Shooting,
+        })
+    } else if hands.aiming && controller.current_action.is_none() {
+        Some(AnimationState::Aiming)
+    } else {
+        None
+    };
+    // Standing still, the whole body can go into the action - otherwise only the arms do.
+    let standing_still = speed <= 0.01 && controller.basis_memory.standing_on_entity().is_some();
+    let layered = upper_body_state.filter(|_| !standing_still);
+    upper_body_layer.update(
+        &mut animation_player,
+        &animation_nodes,
+        layered,
+        time.delta_secs(),
+    );
+
+    let current_status_for_animating = if let Some(knockdown) = knockdown {
+        match knockdown {
+            Knockdown::Ragdoll { .. } => AnimationState::Ragdolling,
+            Knockdown::StandingUp => AnimationState::StandingUp,
+        }
+    } else if let Some(staggered) = staggered {
+        if staggered.heavy {
+            AnimationState::Staggering
+        } else {
+            AnimationState::Flinching
+        }
+    } else if let Some(emoting) = emoting {
+        match emoting.phase {
+            EmotePhase::Entering => AnimationState::EnteringEmote(emoting.emote),
+            EmotePhase::Holding => AnimationState::HoldingEmote(emoting.emote),
+            EmotePhase::Exiting => AnimationState::ExitingEmote(emoting.emote),
+        }
+    } else {
+        match controller.current_action.as_ref() {
+            Some(
+                ControlSchemeActionState::Jump(state) | ControlSchemeActionState::WallJump(state),
+            ) => match state.memory {
+                TnuaBuiltinJumpMemory::NoJump => return,
+                TnuaBuiltinJumpMemory::StartingJump { .. } => AnimationState::Jumping,
+                TnuaBuiltinJumpMemory::SlowDownTooFastSlopeJump { .. } => AnimationState::Jumping,
+                TnuaBuiltinJumpMemory::MaintainingJump { .. } => AnimationState::Jumping,
+                TnuaBuiltinJumpMemory::StoppedMaintainingJump => AnimationState::Jumping,
+                TnuaBuiltinJumpMemory::FallSection => AnimationState::Falling,
+            },
+            Some(ControlSchemeActionState::Dash(state)) => {
+                // The dash turns the character towards its `desired_forward`, so that is what the
+                // dodge direction is relative to.
+                let forward = state.input.desired_forward.map_or(facing, |dir| *dir);
+                AnimationState::Dashing(Heading::of(state.input.displacement.f32(), forward))
+            }
+            Some(ControlSchemeActionState::Knockback(_)) => AnimationState::Flinching,
+            Some(ControlSchemeActionState::Climb(state)) => {
+                if state.input.desired_climb_motion == Vec3::ZERO {
+                    AnimationState::Hanging
+                } else {
+                    AnimationState::ClimbingUp
+                }
+            }
+            Some(ControlSchemeActionState::WallSlide(_)) => AnimationState::WallSliding,
+            Some(ControlSchemeActionState::Kick(_)) => AnimationState::Kicking,
+            Some(ControlSchemeActionState::Spin(_)) => AnimationState::Spinning,
+            Some(ControlSchemeActionState::Crouch(..)) => {
+                if 0.01 < speed {
+                    AnimationState::Sneaking(speed)
+                } else {
+                    AnimationState::Crouching
+                }
+            }
+            Some(ControlSchemeActionState::Punch(_)) | None => {
+                let landing = match animating_state.get() {
+                    Some(AnimationState::Falling) => true,
+                    Some(AnimationState::Landing) => animation_nodes
+                        .get("Jump_Land")
+                        .and_then(|node| animation_player.animation(node))
+                        .is_some_and(|animation| !animation.is_finished()),
+                    _ => false,
+                };
+                // Turning in place: no motion, but still facing away from where it is told to.
+                let turning = controller
+                    .basis
+                    .desired_forward
+                    .filter(|_| controller.basis.desired_motion == Vec3::ZERO)
+                    .map(|desired| Heading::of(*desired, facing))
+                    .filter(|heading| *heading != Heading::Forward);
+
+                if let Some(state) = upper_body_state.filter(|_| standing_still) {
+                    state
+                } else if controller.basis_memory.standing_on_entity().is_none() {
+                    AnimationState::Falling
+                } else if 0.01 < speed {
+                    match Heading::of(velocity.f32(), facing) {
+                        Heading::Forward => AnimationState::Running(speed),
+                        Heading::Backward => AnimationState::WalkingBackwards(speed),
+                        Heading::Left => AnimationState::StrafingLeft(speed),
+                        Heading::Right => AnimationState::StrafingRight(speed),
+                    }
+                } else if landing {
+                    AnimationState::Landing
+                } else if let Some(heading) = turning {
+                    // Shuffle the feet towards the side the character turns to.
+                    match heading {
+                        Heading::Right => AnimationState::StrafingRight(0.5 * STRAFE_CLIP_SPEED),
+                        _ => AnimationState::StrafingLeft(0.5 * STRAFE_CLIP_SPEED),
+                    }
+                } else {
+                    AnimationState::Standing
+                }
+            }
+        }
+    };
+
+    let animating_directive = animating_state.update_by_discriminant(current_status_for_animating);
+
+    match animating_directive {
+        TnuaAnimatingStateDirective::Maintain { state } => match state {
+            AnimationState::Running(speed) => {
+                for (clip, clip_speed) in [
+                    ("Walking_A", WALK_CLIP_SPEED),
+                    ("Running_A", RUN_CLIP_SPEED),
+                ] {
+                    if let Some(animation) = animation_nodes
+                        .get(clip)
+                        .and_then(|node| animation_player.animation_mut(node))
+                    {
+                        animation.set_speed(*speed / clip_speed);
+                    }
+                }
+                locomotion_blend.0 = run_blend(*speed);
+            }
+            AnimationState::WalkingBackwards(speed) => {
+                if let Some(animation) = animation_nodes
+                    .get("Walking_Backwards")
+                    .and_then(|node| animation_player.animation_mut(node))
+                {
+                    animation.set_speed(*speed / WALK_CLIP_SPEED);
+                }
+            }
+            AnimationState::StrafingLeft(speed) => {
+                if let Some(animation) = animation_nodes
+                    .get("Running_Strafe_Left")
+                    .and_then(|node| animation_player.animation_mut(node))
+                {
+                    animation.set_speed(*speed / STRAFE_CLIP_SPEED);
+                }
+            }
+            AnimationState::StrafingRight(speed) => {
+                if let Some(animation) = animation_nodes
+                    .get("Running_Strafe_Right")
+                    .and_then(|node| animation_player.animation_mut(node))
+                {
+                    animation.set_speed(*speed / STRAFE_CLIP_SPEED);
+                }
+            }
+            AnimationState::Sneaking(speed) => {
+                if let Some(animation) = animation_nodes
+                    .get("Walking_C")
+                    .and_then(|node| animation_player.animation_mut(node))
+                {
+                    animation.set_speed(*speed / SNEAK_CLIP_SPEED);
+                }
+            }
+            _ => {}
+        },
+        TnuaAnimatingStateDirective::Alter { old_state, state } => {
+            let (clip, speed, repeat) = state.clip();
+
+            let Some(node) = animation_nodes.get(clip) else {
+                return;
+            };
+            let fade = crossfade_duration(old_state.as_ref(), state);
+            // The fade out starts from the weight of the main clip, which only holds the walking
+            // share of the locomotion state.
+            if let (Some(walk), Some(run)) = (
+                animation_nodes.get("Walking_A"),
+                animation_nodes.get("Running_A"),
+            ) && let Some(run_weight) = animation_player.animation(run).map(|run| run.weight())
+                && let Some(walk) = animation_player.animation_mut(walk)
+            {
+                walk.set_weight(walk.weight() + run_weight);
+            }
+            let animation = transitions.play(&mut animation_player, node, fade);
+            animation.set_speed(speed);
+            if repeat {
+                animation.repeat();
+            }
+            if let AnimationState::Running(speed) = state {
+                // Started together with `Walking_A` so their steps line up;
+                // `follow_locomotion_weight` fades it along.
+                if let Some(run) = animation_nodes.get("Running_A") {
+                    animation_player
+                        .start(run)
+                        .set_speed(*speed / RUN_CLIP_SPEED)
+                        .repeat();
+                }
+                locomotion_blend.0 = run_blend(*speed);
+            }
+        }
+    }
+}
+
+/// Weight of `Running_A` against `Walking_A` in the locomotion state, from the ground speed.
+#[derive(Component, Default)]
+struct LocomotionBlend(f32);
+
+/// `AnimationTransitions` only fades `Walking_A`, the main clip of the locomotion state, so the
+/// weight it gives is split between the two clips - and `Running_A` stops with it.
+fn follow_locomotion_weight(
+    mut animation_player_query: Query<(&mut AnimationPlayer, &LocomotionBlend)>,
+    animation_nodes: Option<Res<Animations>>,
+) {
+    let Some((walk, run)) =
+        animation_nodes.and_then(|nodes| Some((nodes.get("Walking_A")?, nodes.get("Running_A")?)))
+    else {
+        return;
+    };
+    for (mut animation_player, blend) in &mut animation_player_query {
+        match animation_player
+            .animation(walk)
+            .map(|animation| animation.weight())
+        {
+            Some(weight) => {
+                if let Some(animation) = animation_player.animation_mut(walk) {
+                    animation.set_weight(weight * (1.0 - blend.0));
+                }
+                if let Some(animation) = animation_player.animation_mut(run) {
+                    animation.set_weight(weight * blend.0);
+                }
+            }
+            None => {
+                animation_player.stop(run);
+            }
+        }
+    }
+}
+
+/// The ground is probed from this far above the floor the character stands on, so a foot can
+/// be raised onto a step this high.
+const MAX_FOOT_RAISE: f32 = 0.45;
+const MAX_FOOT_DROP: f32 = 0.45;
+const FOOT_IK_BLEND_SPEED: f32 = 8.0;
+const HIPS_BONE: &str = "hips";
+/// Upper leg, lower leg and foot, for the left and the right leg.
+const LEG_BONES: [[&str; 3]; 2] = [
+    ["upperleg.l", "lowerleg.l", "foot.l"],
+    ["upperleg.r", "lowerleg.r", "foot.r"],
+];
+
+/// Bends the legs so the feet plant on whatever is under them instead of the flat ground the
+/// animations were made for.
+#[derive(Component)]
+struct FootIk {
+    hips: Entity,
+    legs: [[Entity; 3]; 2],
+    weight: f32,
+    pelvis_offset: f32,
+    /// Bones written last frame, with their animated and their corrected transforms.
+    applied: Vec<(Entity, Transform, Transform)>,
+}
+
+/// Finds the leg bones once the character's scene has spawned under the player.
+fn setup_foot_ik(
+    scene_ready: On<SceneInstanceReady>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    parents: Query<&ChildOf>,
+    children: Query<&Children>,
+    names: Query<&Name>,
 ) {
-    commands.spawn((Camera3d::default(), ThirdPersonCamera::default()));
+    let Ok(child_of) = parents.get(scene_ready.entity) else {
+        return;
+    };
+    let player = child_of.parent();
+    let find_bone = |bone: &str| {
+        let found = children
+            .iter_descendants(player)
+            .find(|entity| names.get(*entity).is_ok_and(|name| name.as_str() == bone));
+        if found.is_none() {
+            warn!("The character has no {bone} bone, so its feet won't be planted");
+        }
+        found
+    };
+    let Some(hips) = find_bone(HIPS_BONE) else {
+        return;
+    };
+    let mut legs = [[Entity::PLACEHOLDER; 3]; 2];
+    for (leg, bones) in legs.iter_mut().zip(LEG_BONES) {
+        for (entity, bone) in leg.iter_mut().zip(bones) {
+            let Some(found) = find_bone(bone) else {
+                return;
+            };
+            *entity = found;
+        }
+    }
+    commands.entity(player).insert(FootIk {
+        hips,
+        legs,
+        weight: 0.0,
+        pelvis_offset: 0.0,
+        applied: Vec::new(),
+    });
+}
 
-    commands.spawn((PointLight::default(), Transform::from_xyz(5.0, 5.0, 5.0)));
+/// The world transform of a bone, from the transforms the animation just wrote - the
+/// `GlobalTransform`s are not propagated yet at this point.
+fn bone_global_transform(
+    bone: Entity,
+    player: Entity,
+    player_transform: &Transform,
+    transforms: &Query<&mut Transform, Without<Player>>,
+    parents: &Query<&ChildOf>,
+) -> Transform {
+    let mut global = Transform::IDENTITY;
+    let mut current = bone;
+    while current != player {
+        let Ok(local) = transforms.get(current) else {
+            break;
+        };
+        global = local.mul_transform(global);
+        let Ok(child_of) = parents.get(current) else {
+            break;
+        };
+        current = child_of.parent();
+    }
+    player_transform.mul_transform(global)
+}
 
-    commands.spawn((
-        DirectionalLight {
-            illuminance: 4000.0,
-            shadows_enabled: true,
-            ..default()
-        },
-        Transform::default().looking_at(-Vec3::Y, Vec3::Z),
-    ));
+/// Rotates a hip-knee-ankle chain so the ankle lands on `target`, keeping the knee bending the
+/// way it already does. Returns the world-space rotations to apply to the upper and lower leg.
+fn solve_two_bone_ik(hip: Vec3, knee: Vec3, ankle: Vec3, target: Vec3) -> (Quat, Quat) {
+    let upper_length = hip.distance(knee);
+    let lower_length = knee.distance(ankle);
+    let Some(direction) = (target - hip).try_normalize() else {
+        return (Quat::IDENTITY, Quat::IDENTITY);
+    };
+    let Some(bend) = (knee - hip)
+        .reject_from_normalized(direction)
+        .try_normalize()
+    else {
+        return (Quat::IDENTITY, Quat::IDENTITY);
+    };
+    let distance = hip.distance(target).clamp(
+        (upper_length - lower_length).abs() + 0.001,
+        upper_length + lower_length - 0.001,
+    );
 
-    commands.spawn((
-        Mesh3d(meshes.add(Plane3d::default().mesh().size(512.0, 512.0))),
-        MeshMaterial3d(materials.add(Color::WHITE)),
-        RigidBody::Static,
-        Collider::half_space(Vec3::Y),
-    ));
+    let cos = ((upper_length.powi(2) + distance.powi(2) - lower_length.powi(2))
+        / (2.0 * upper_length * distance))
+        .clamp(-1.0, 1.0);
+    let sin = (1.0 - cos * cos).sqrt();
+    let new_knee = hip + upper_length * (cos * direction + sin * bend);
 
-    commands.spawn((
-        Mesh3d(meshes.add(Cuboid::new(10.0, 1.0, 10.0))),
-        MeshMaterial3d(materials.add(Color::from(css::GRAY))),
-        Transform::from_xyz(-6.0, 2.0, 0.0),
-        RigidBody::Static,
-        Collider::cuboid(10.0, 1.0, 10.0),
-    ));
+    let upper_rotation =
+        Quat::from_rotation_arc((knee - hip).normalize(), (new_knee - hip).normalize());
+    let moved_ankle = new_knee + upper_rotation * (ankle - knee);
+    let lower_rotation = Quat::from_rotation_arc(
+        (moved_ankle - new_knee).normalize(),
+        (hip + distance * direction - new_knee).normalize(),
+    );
+    (upper_rotation, lower_rotation)
+}
+
+#[allow(clippy::type_complexity)]
+fn apply_foot_ik(
+    mut player_query: Query<
+        (
+            Entity,
+            &TnuaController<ControlScheme>,
+            &Transform,
+            &mut FootIk,
+            Has<Knockdown>,
+        ),
+        With<Player>,
+    >,
+    mut transforms: Query<&mut Transform, Without<Player>>,
+    parents: Query<&ChildOf>,
+    spatial_query: SpatialQuery,
+    time: Res<Time>,
+) {
+    let Ok((player, controller, player_transform, mut foot_ik, knocked_down)) =
+        player_query.single_mut()
+    else {
+        return;
+    };
+
+    // Bones the animation did not touch this frame would otherwise pile up corrections.
+    for (bone, animated, corrected) in foot_ik.applied.drain(..) {
+        if let Ok(mut transform) = transforms.get_mut(bone)
+            && *transform == corrected
+        {
+            *transform = animated;
+        }
+    }
+
+    let grounded = !knocked_down
+        && controller.basis_memory.standing_on_entity().is_some()
+        && !matches!(
+            controller.action_discriminant(),
+            Some(ControlSchemeActionDiscriminant::Jump | ControlSchemeActionDiscriminant::Dash)
+        );
+    let blend = (FOOT_IK_BLEND_SPEED * time.delta_secs()).min(1.0);
+    foot_ik.weight = foot_ik.weight.lerp(if grounded { 1.0 } else { 0.0 }, blend);
+
+    // How far the ground under each foot is above (or below) the floor the controller is on.
+    let global = |bone: Entity, transforms: &Query<&mut Transform, Without<Player>>| {
+        bone_global_transform(bone, player, player_transform, transforms, &parents)
+    };
+    let filter = solid_filter([player]);
+    let floor = player_transform.translation.y;
+    let mut offsets = [0.0; 2];
+    let mut normals = [Vec3::Y; 2];
+    for (i, [_, _, foot]) in foot_ik.legs.iter().enumerate() {
+        let ankle = global(*foot, &transforms).translation;
+        if let Some(hit) = spatial_query.cast_ray(
+            ankle.with_y(floor + MAX_FOOT_RAISE),
+            Dir3::NEG_Y,
+            MAX_FOOT_RAISE + MAX_FOOT_DROP,
+            true,
+            &filter,
+        ) {
+            offsets[i] = (MAX_FOOT_RAISE - hit.distance) * foot_ik.weight;
+            normals[i] = Vec3::Y.lerp(hit.normal, foot_ik.weight);
+        }
+    }
+
+    // Lower the pelvis so the leg over the lower ground can still reach it.
+    let pelvis_target = offsets[0].min(offsets[1]).min(0.0);
+    foot_ik.pelvis_offset = foot_ik.pelvis_offset.lerp(pelvis_target, blend);
+    let hips = foot_ik.hips;
+    if let Ok(child_of) = parents.get(hips) {
+        let parent = global(child_of.parent(), &transforms);
+        if let Ok(mut transform) = transforms.get_mut(hips) {
+            let animated = *transform;
+            transform.translation +=
+                parent.rotation.inverse() * Vec3::Y * foot_ik.pelvis_offset / parent.scale;
+            foot_ik.applied.push((hips, animated, *transform));
+        }
+    }
+
+    let hips_rotation = global(hips, &transforms).rotation;
+    let legs = foot_ik.legs;
+    for (([upper, lower, foot], offset), normal) in legs.into_iter().zip(offsets).zip(normals) {
+        let upper_global = global(upper, &transforms);
+        let lower_global = global(lower, &transforms);
+        let foot_global = global(foot, &transforms);
+        let (upper_rotation, lower_rotation) = solve_two_bone_ik(
+            upper_global.translation,
+            lower_global.translation,
+            foot_global.translation,
+            foot_global.translation + Vec3::Y * (offset - foot_ik.pelvis_offset),
+        );
+
+        let upper_world = upper_rotation * upper_global.rotation;
+        let lower_world = lower_rotation * upper_rotation * lower_global.rotation;
+        // Keep the foot's own orientation, only tilted onto the surface it stands on.
+        let foot_world =
+            Quat::from_rotation_arc(Vec3::Y, normal.normalize()) * foot_global.rotation;
+        for (bone, parent_world, world) in [
+            (upper, hips_rotation, upper_world),
+            (lower, upper_world, lower_world),
+            (foot, lower_world, foot_world),
+        ] {
+            if let Ok(mut transform) = transforms.get_mut(bone) {
+                let animated = *transform;
+                transform.rotation = parent_world.inverse() * world;
+                foot_ik.applied.push((bone, animated, *transform));
+            }
+        }
+    }
+}
+
+/// Walking off a ledge higher than this knocks the character down. Jumps never do.
+const RAGDOLL_FALL_HEIGHT: f32 = 2.0;
+const RAGDOLL_MIN_DURATION: f32 = 1.5;
+const RAGDOLL_MAX_DURATION: f32 = 5.0;
+const RAGDOLL_REST_SPEED: f32 = 0.3;
+
+#[derive(PhysicsLayer, Default)]
+enum GameLayer {
+    #[default]
+    Default,
+    /// Ragdoll limbs, which collide with the world but not with each other.
+    Ragdoll,
+    /// Trigger zones, which spatial queries look straight through.
+    Trigger,
+}
+
+/// A spatial query filter that only sees solid things - no trigger zones.
+fn solid_filter(excluded: impl IntoIterator<Item = Entity>) -> SpatialQueryFilter {
+    SpatialQueryFilter::from_mask([GameLayer::Default, GameLayer::Ragdoll])
+        .with_excluded_entities(excluded)
+}
+
+struct RagdollBone {
+    name: &'static str,
+    parent: Option<&'static str>,
+    /// The bone the limb's capsule reaches to, straight up when there is none.
+    tip: Option<&'static str>,
+    radius: f32,
+    /// How far the limb can swing away from its pose at the moment of the knockdown.
+    swing: f32,
+}
+
+/// The limbs of the ragdoll, parents first. The hips must come first - they carry the rest.
+const RAGDOLL_BONES: [RagdollBone; 11] = [
+    RagdollBone {
+        name: "hips",
+        parent: None,
+        tip: Some("spine"),
+        radius: 0.25,
+        swing: 0.0,
+    },
+    RagdollBone {
+        name: "chest",
+        parent: Some("hips"),
+        tip: Some("head"),
+        radius: 0.3,
+        swing: 0.6,
+    },
+    RagdollBone {
+        name: "head",
+        parent: Some("chest"),
+        tip: None,
+        radius: 0.35,
+        swing: 0.8,
+    },
+    RagdollBone {
+        name: "upperarm.l",
+        parent: Some("chest"),
+        tip: Some("lowerarm.l"),
+        radius: 0.1,
+        swing: 1.5,
+    },
+    RagdollBone {
+        name: "lowerarm.l",
+        parent: Some("upperarm.l"),
+        tip: Some("wrist.l"),
+        radius: 0.09,
+        swing: 1.2,
+    },
+    RagdollBone {
+        name: "upperarm.r",
+        parent: Some("chest"),
+        tip: Some("lowerarm.r"),
+        radius: 0.1,
+        swing: 1.5,
+    },
+    RagdollBone {
+        name: "lowerarm.r",
+        parent: Some("upperarm.r"),
+        tip: Some("wrist.r"),
+        radius: 0.09,
+        swing: 1.2,
+    },
+    RagdollBone {
+        name: "upperleg.l",
+        parent: Some("hips"),
+        tip: Some("lowerleg.l"),
+        radius: 0.12,
+        swing: 1.0,
+    },
+    RagdollBone {
+        name: "lowerleg.l",
+        parent: Some("upperleg.l"),
+        tip: Some("foot.l"),
+        radius: 0.1,
+        swing: 1.2,
+    },
+    RagdollBone {
+        name: "upperleg.r",
+        parent: Some("hips"),
+        tip: Some("lowerleg.r"),
+        radius: 0.12,
+        swing: 1.0,
+    },
+    RagdollBone {
+        name: "lowerleg.r",
+        parent: Some("upperleg.r"),
+        tip: Some("foot.r"),
+        radius: 0.1,
+        swing: 1.2,
+    },
+];
+
+/// Whether heavy hits and falls knock the character down. Toggled with K.
+#[derive(Resource)]
+struct KnockdownsEnabled(bool);
+
+impl Default for KnockdownsEnabled {
+    fn default() -> Self {
+        Self(true)
+    }
 }
 
+/// Present on the player from the moment it is knocked down until it is back on its feet. Input
+/// is ignored throughout.
 #[derive(Component)]
-struct Player;
+enum Knockdown {
+    /// Limp, each bone in [`RAGDOLL_BONES`] driven by its own rigid body.
+    Ragdoll {
+        /// The bone name, the bone and the body driving it.
+        limbs: Vec<(&'static str, Entity, Entity)>,
+        joints: Vec<Entity>,
+        elapsed: Stopwatch,
+    },
+    /// Back on the capsule, playing `Lie_StandUp`.
+    StandingUp,
+}
 
-fn setup_player(
+/// The highest point since the player last stood on something, and whether it jumped to get there.
+#[derive(Component, Default)]
+struct FallTracking {
+    peak: Option<f32>,
+    jumped: bool,
+}
+
+/// Impacts (in N*s) on the player from dominoes and balls that make it flinch, stagger, or - if
+/// knockdowns are on - go down.
+const HIT_IMPULSE: f32 = 2.0;
+const HEAVY_HIT_IMPULSE: f32 = 6.0;
+const KNOCKDOWN_IMPULSE: f32 = 12.0;
+const HIT_DURATION: f32 = 0.4;
+const HEAVY_HIT_DURATION: f32 = 0.8;
+/// How hard the player gets shoved back, in m/s per N*s of impact.
+const HIT_KNOCKBACK: f32 = 0.6;
+const MAX_HIT_KNOCKBACK: f32 = 8.0;
+/// Running into something faster than this is the player's own doing, not a hit.
+const HIT_SELF_INFLICTED_SPEED: f32 = 1.0;
+
+/// Sent for hits hard enough to ragdoll the player.
+#[derive(Message)]
+struct HeavyImpact {
+    push: Vec3,
+}
+
+/// Present on the player while it recovers from a hit. Input is ignored until it wears off.
+#[derive(Component)]
+struct Staggered {
+    timer: Timer,
+    heavy: bool,
+}
+
+/// Turns impacts from dominoes and balls into hit reactions, by the impulse they hit the player
+/// with. Runs right after the physics step that reported them, while the contacts still hold it.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn detect_hits(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut control_scheme_configs: ResMut<Assets<ControlSchemeConfig>>,
-    mut graphs: ResMut<Assets<AnimationGraph>>,
+    time: Res<Time>,
+    mut collision_starts: MessageReader<CollisionStart>,
+    mut heavy_impacts: MessageWriter<HeavyImpact>,
+    collisions: Collisions,
+    knockdowns: Res<KnockdownsEnabled>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut TnuaController<ControlScheme>,
+            &Transform,
+            &LinearVelocity,
+            Option<&mut Staggered>,
+        ),
+        (With<Player>, Without<Knockdown>),
+    >,
+    hitters: Query<&Transform, With<Domino>>,
 ) {
-    let clips = (0..ANIMATIONS.len())
-        .into_iter()
-        .map(|i| asset_server.load(GltfAssetLabel::Animation(i).from_asset(GLTF_PATH)))
-        .collect::<Vec<_>>();
-
-    let (graph, node_indices) = AnimationGraph::from_clips(clips);
+    let Ok((player, mut controller, transform, velocity, staggered)) = player_query.single_mut()
+    else {
+        collision_starts.clear();
+        return;
+    };
+    if let Some(mut staggered) = staggered
+        && staggered.timer.tick(time.delta()).is_finished()
+    {
+        commands.entity(player).remove::<Staggered>();
+    }
 
-    let mut animations = HashMap::<String, AnimationNodeIndex>::new();
-    for i in 0..ANIMATIONS.len() {
-        let node_index = node_indices[i];
-        let name = ANIMATIONS[i].to_string();
-        animations.insert(name, node_index);
+    let mut hardest = None::<(f32, Vec3)>;
+    for collision in collision_starts.read() {
+        let other = if collision.collider1 == player {
+            collision.collider2
+        } else if collision.collider2 == player {
+            collision.collider1
+        } else {
+            continue;
+        };
+        let Ok(hitter) = hitters.get(other) else {
+            continue;
+        };
+        let Some(impulse) = collisions
+            .get(player, other)
+            .map(|contacts| contacts.total_normal_impulse_magnitude().f32())
+        else {
+            continue;
+        };
+        let away = (transform.translation - hitter.translation)
+            .with_y(0.0)
+            .normalize_or_zero();
+        if HIT_SELF_INFLICTED_SPEED < velocity.dot(-away) {
+            continue;
+        }
+        if hardest.is_none_or(|(hardest, _)| hardest < impulse) {
+            hardest = Some((impulse, away));
+        }
     }
+    let Some((impulse, away)) = hardest.filter(|(impulse, _)| HIT_IMPULSE <= *impulse) else {
+        return;
+    };
 
-    let graph_handle = graphs.add(graph);
-    commands.insert_resource(Animations {
-        animations,
-        graph_handle,
-    });
+    if knockdowns.0 && KNOCKDOWN_IMPULSE <= impulse {
+        heavy_impacts.write(HeavyImpact {
+            push: away * (HIT_KNOCKBACK * impulse).min(MAX_HIT_KNOCKBACK),
+        });
+        return;
+    }
 
+    let heavy = HEAVY_HIT_IMPULSE <= impulse;
+    controller.action_interrupt(ControlScheme::Knockback(TnuaBuiltinKnockback {
+        shove: (away * (HIT_KNOCKBACK * impulse).min(MAX_HIT_KNOCKBACK)).adjust_precision(),
+        // Face whatever hit the player, so it reels back away from it.
+        force_forward: Dir3::new(-away).ok(),
+    }));
     commands
-        .spawn((
-            Player,
-            Transform::from_xyz(0.0, 2.0, 0.0),
-            TnuaAnimatingState::<AnimationState>::default(),
-            RigidBody::Dynamic,
-            Collider::capsule_endpoints(0.5, Vec3::Y * (1.0 + 0.5), Vec3::Y * 0.5),
-            TnuaController::<ControlScheme>::default(),
-            TnuaConfig::<ControlScheme>(control_scheme_configs.add(ControlSchemeConfig::default())),
-            TnuaAvian3dSensorShape(Collider::cylinder(0.49, 0.0)),
-            LockedAxes::ROTATION_LOCKED.unlock_rotation_y(),
-        ))
-        .with_children(|parent| {
-            parent
-                .spawn((
-                    SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(GLTF_PATH))),
-                    Transform::from_rotation(Quat::from_rotation_y(PI)),
-                ))
-                .observe(play_animation_when_ready);
+        .entity(player)
+        .remove::<Emoting>()
+        .insert(Staggered {
+            timer: Timer::from_seconds(
+                if heavy {
+                    HEAVY_HIT_DURATION
+                } else {
+                    HIT_DURATION
+                },
+                TimerMode::Once,
+            ),
+            heavy,
         });
 }
 
-fn play_animation_when_ready(
-    scene_ready: On<SceneInstanceReady>,
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn detect_knockdowns(
     mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut enabled: ResMut<KnockdownsEnabled>,
+    mut impacts: MessageReader<HeavyImpact>,
+    mut player_query: Query<
+        (
+            Entity,
+            &TnuaController<ControlScheme>,
+            &Transform,
+            &LinearVelocity,
+            &mut FallTracking,
+            &mut Hands,
+        ),
+        (With<Player>, Without<Knockdown>),
+    >,
     children: Query<&Children>,
-    mut players: Query<(Entity, &mut AnimationPlayer)>,
-    animations: Res<Animations>,
+    bones: Query<(&Name, &GlobalTransform)>,
 ) {
-    for child in children.iter_descendants(scene_ready.entity) {
-        if let Ok(_) = players.get_mut(child) {
-            commands
-                .entity(child)
-                .insert(AnimationGraphHandle(animations.graph_handle.clone()));
+    if keyboard.just_pressed(settings.key_bindings.toggle_knockdowns) {
+        enabled.0 = !enabled.0;
+        info!("Knockdowns {}", if enabled.0 { "on" } else { "off" });
+    }
+    let Ok((player, controller, transform, velocity, mut fall, mut hands)) =
+        player_query.single_mut()
+    else {
+        impacts.clear();
+        return;
+    };
+
+    let mut knock = impacts.read().last().map(|impact| impact.push);
+
+    let height = transform.translation.y;
+    if controller.basis_memory.standing_on_entity().is_some() {
+        if let Some(peak) = fall.peak.take()
+            && !fall.jumped
+            && RAGDOLL_FALL_HEIGHT < peak - height
+        {
+            knock = Some(Vec3::ZERO);
         }
+        fall.jumped = false;
+    } else {
+        // Hanging on or sliding down a wall breaks the fall.
+        let clinging = matches!(
+            controller.action_discriminant(),
+            Some(
+                ControlSchemeActionDiscriminant::Climb | ControlSchemeActionDiscriminant::WallSlide
+            )
+        );
+        fall.peak = Some(match fall.peak {
+            Some(peak) if !clinging => peak.max(height),
+            _ => height,
+        });
+        fall.jumped |= matches!(
+            controller.action_discriminant(),
+            Some(ControlSchemeActionDiscriminant::Jump | ControlSchemeActionDiscriminant::WallJump)
+        );
     }
-}
 
-fn apply_controls(
-    keyboard: Res<ButtonInput<KeyCode>>,
-    camera: Single<&Transform, With<Camera3d>>,
-    mut query: Query<(&mut TnuaController<ControlScheme>,)>,
-) {
-    let Ok((mut controller,)) = query.single_mut() else {
+    if !enabled.0 {
+        return;
+    }
+    let Some(knock) = knock else {
         return;
     };
-    controller.initiate_action_feeding();
 
-    let mut direction = Vec3::ZERO;
-    if keyboard.pressed(KeyCode::KeyW) {
-        direction.z += 1.0;
+    let find_bone = |name: &str| {
+        children.iter_descendants(player).find_map(|entity| {
+            let (bone, global) = bones.get(entity).ok()?;
+            (bone.as_str() == name).then(|| (entity, global.compute_transform()))
+        })
+    };
+    let mut limbs: Vec<(&str, Entity, Entity)> = Vec::new();
+    let mut joints = Vec::new();
+    for bone in &RAGDOLL_BONES {
+        let Some((entity, global)) = find_bone(bone.name) else {
+            continue;
+        };
+        let tip = bone
+            .tip
+            .and_then(find_bone)
+            .map_or(global.translation + 0.5 * Vec3::Y, |(_, tip)| {
+                tip.translation
+            });
+        let body = commands
+            .spawn((
+                DespawnOnExit(InCourse),
+                Transform::from_translation(global.translation).with_rotation(global.rotation),
+                RigidBody::Dynamic,
+                Collider::capsule_endpoints(
+                    bone.radius,
+                    Vec3::ZERO,
+                    global.rotation.inverse() * (tip - global.translation),
+                ),
+                CollisionLayers::new(GameLayer::Ragdoll, [GameLayer::Default]),
+                LinearVelocity(velocity.0 + knock),
+            ))
+            .id();
+        let parent_body = limbs
+            .iter()
+            .find(|(name, ..)| Some(*name) == bone.parent)
+            .map(|(_, _, parent_body)| *parent_body);
+        if let Some(parent_body) = parent_body {
+            let direction = (tip - global.translation).normalize_or(Vec3::Y);
+            joints.push(
+                commands
+                    .spawn((
+                        DespawnOnExit(InCourse),
+                        SphericalJoint::new(parent_body, body)
+                            .with_anchor(global.translation.adjust_precision())
+                            .with_basis(Quat::from_rotation_arc(Vec3::Y, direction))
+                            .with_swing_limits(-bone.swing, bone.swing)
+                            .with_twist_limits(-0.4, 0.4),
+                    ))
+                    .id(),
+            );
+        }
+        limbs.push((bone.name, entity, body));
     }
-    if keyboard.pressed(KeyCode::KeyS) {
-        direction.z -= 1.0;
+
+    if let Some(held) = hands.held.take() {
+        commands
+            .entity(held)
+            .remove::<(Held, RigidBodyDisabled, ColliderDisabled)>();
     }
-    if keyboard.pressed(KeyCode::KeyA) {
-        direction.x -= 1.0;
+    hands.aiming = false;
+    hands.charge = None;
+    hands.gesture = None;
+    *fall = FallTracking::default();
+    commands.entity(player).remove::<Emoting>().insert((
+        Knockdown::Ragdoll {
+            limbs,
+            joints,
+            elapsed: Stopwatch::new(),
+        },
+        RigidBodyDisabled,
+        ColliderDisabled,
+    ));
+}
+
+/// Copies the ragdoll bodies onto the bones, over whatever the animation posed.
+fn pose_ragdoll(
+    mut player_query: Query<(Entity, &mut Transform, &Knockdown), With<Player>>,
+    mut transforms: Query<&mut Transform, Without<Player>>,
+    parents: Query<&ChildOf>,
+    spatial_query: SpatialQuery,
+) {
+    let Ok((player, mut player_transform, knockdown)) = player_query.single_mut() else {
+        return;
+    };
+    let Knockdown::Ragdoll { limbs, .. } = knockdown else {
+        return;
+    };
+
+    // Keep the disabled capsule under the hips, so the camera follows and standing up starts
+    // from the right spot.
+    if let Some(hips) = limbs
+        .first()
+        .and_then(|(_, _, body)| transforms.get(*body).ok())
+    {
+        let hips = hips.translation;
+        let filter = SpatialQueryFilter::from_mask(GameLayer::Default);
+        if let Some(hit) = spatial_query.cast_ray(hips, Dir3::NEG_Y, 10.0, true, &filter) {
+            player_transform.translation = hips.with_y(hips.y - hit.distance);
+        }
     }
-    if keyboard.pressed(KeyCode::KeyD) {
-        direction.x += 1.0;
+
+    for (index, (_, bone, body)) in limbs.iter().enumerate() {
+        let Ok(world) = transforms.get(*body).copied() else {
+            continue;
+        };
+        let Ok(child_of) = parents.get(*bone) else {
+            continue;
+        };
+        let parent = bone_global_transform(
+            child_of.parent(),
+            player,
+            &player_transform,
+            &transforms,
+            &parents,
+        );
+        let Ok(mut transform) = transforms.get_mut(*bone) else {
+            continue;
+        };
+        transform.rotation = parent.rotation.inverse() * world.rotation;
+        if index == 0 {
+            transform.translation =
+                parent.rotation.inverse() * (world.translation - parent.translation) / parent.scale;
+        }
     }
+}
 
-    if direction != Vec3::ZERO {
-        let forward = *camera.forward();
-        let forward = Vec3::new(forward.x, 0.0, forward.z).normalize_or_zero();
-        let right = *camera.right();
-        let right = Vec3::new(right.x, 0.0, right.z).normalize_or_zero();
-        direction = (forward * direction.z + right * direction.x).normalize_or_zero();
+#[allow(clippy::type_complexity)]
+fn recover_from_knockdown(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_query: Query<(Entity, &mut Transform, &mut Knockdown), With<Player>>,
+    bodies: Query<(&Transform, &LinearVelocity), Without<Player>>,
+    animation_player: Query<&AnimationPlayer>,
+    animations: Option<Res<Animations>>,
+) {
+    let Ok((player, mut transform, mut knockdown)) = player_query.single_mut() else {
+        return;
+    };
+    match &mut *knockdown {
+        Knockdown::Ragdoll {
+            limbs,
+            joints,
+            elapsed,
+        } => {
+            elapsed.tick(time.delta());
+            let resting = limbs
+                .iter()
+                .filter_map(|(_, _, body)| bodies.get(*body).ok())
+                .all(|(_, velocity)| velocity.length() < RAGDOLL_REST_SPEED);
+            let elapsed = elapsed.elapsed_secs();
+            if elapsed < RAGDOLL_MIN_DURATION || !resting && elapsed < RAGDOLL_MAX_DURATION {
+                return;
+            }
+
+            // Lie_StandUp gets up from the back, ending up facing where the feet were.
+            let limb_position = |limb: &str| {
+                limbs
+                    .iter()
+                    .find(|(name, ..)| *name == limb)
+                    .and_then(|(_, _, body)| bodies.get(*body).ok())
+                    .map(|(transform, _)| transform.translation)
+            };
+            if let (Some(hips), Some(head)) = (limb_position("hips"), limb_position("head"))
+                && let Ok(forward) = Dir3::new((hips - head).with_y(0.0))
+            {
+                transform.look_to(forward, Vec3::Y);
+            }
+
+            for entity in joints.iter().chain(limbs.iter().map(|(_, _, body)| body)) {
+                commands.entity(*entity).despawn();
+            }
+            commands
+                .entity(player)
+                .remove::<(RigidBodyDisabled, ColliderDisabled)>()
+                .insert((LinearVelocity::ZERO, AngularVelocity::ZERO));
+            *knockdown = Knockdown::StandingUp;
+        }
+        Knockdown::StandingUp => {
+            let finished = match (animations, animation_player.single()) {
+                (Some(animations), Ok(animation_player)) => {
+                    animations.is_finished(animation_player, "Lie_StandUp")
+                }
+                _ => true,
+            };
+            if finished {
+                commands.entity(player).remove::<Knockdown>();
+            }
+        }
     }
+}
 
-    let jump = keyboard.pressed(KeyCode::Space);
-    if jump {
-        controller.action(ControlScheme::Jump(TnuaBuiltinJump {
-            allow_in_air: false,
-            ..default()
-        }));
+const SEAT_HEIGHT: f32 = 0.5;
+const SEAT_DEPTH: f32 = 0.6;
+const SEAT_REACH: f32 = 1.5;
+const EMOTE_WHEEL_RADIUS: f32 = 120.0;
+/// How far the mouse has to be pushed before the wheel picks anything.
+const EMOTE_WHEEL_DEAD_ZONE: f32 = 30.0;
+
+/// Something the player can sit on with E. It faces its local forward, with the backrest behind.
+#[derive(Component)]
+struct Seat;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emote {
+    SitChair,
+    SitFloor,
+    LieDown,
+    Cheer,
+}
+
+impl Emote {
+    fn name(self) -> &'static str {
+        match self {
+            Emote::SitChair => "Sit",
+            Emote::SitFloor => "Sit down",
+            Emote::LieDown => "Lie down",
+            Emote::Cheer => "Cheer",
+        }
     }
 
-    let dash = keyboard.pressed(KeyCode::ShiftLeft);
-    if dash {
-        let up_direction = controller.up_direction().unwrap_or(Dir3::Y);
-        controller.action(ControlScheme::Dash(TnuaBuiltinDash {
-            displacement: direction.normalize() + up_direction.adjust_precision(),
-            desired_forward: Dir3::new(direction.f32()).ok(),
-            allow_in_air: true,
-        }));
+    /// The clips for getting into the emote, holding it and getting back out. Emotes without the
+    /// first and last play the middle one once and end on their own.
+    fn clips(self) -> (Option<&'static str>, &'static str, Option<&'static str>) {
+        match self {
+            Emote::SitChair => (
+                Some("Sit_Chair_Down"),
+                "Sit_Chair_Idle",
+                Some("Sit_Chair_StandUp"),
+            ),
+            Emote::SitFloor => (
+                Some("Sit_Floor_Down"),
+                "Sit_Floor_Idle",
+                Some("Sit_Floor_StandUp"),
+            ),
+            Emote::LieDown => (Some("Lie_Down"), "Lie_Idle", Some("Lie_StandUp")),
+            Emote::Cheer => (None, "Cheer", None),
+        }
     }
+}
 
-    let turn_in_place = keyboard.pressed(KeyCode::AltLeft);
+/// The emotes on the wheel, clockwise from the top. Sitting on a chair needs a [`Seat`].
+const WHEEL_EMOTES: [Emote; 3] = [Emote::SitFloor, Emote::LieDown, Emote::Cheer];
 
-    controller.basis = TnuaBuiltinWalk {
-        desired_motion: if turn_in_place { Vec3::ZERO } else { direction },
-        desired_forward: Dir3::new(direction).ok(),
-    };
+#[derive(Clone, Copy, PartialEq)]
+enum EmotePhase {
+    Entering,
+    Holding,
+    Exiting,
 }
 
-fn handle_animating(
-    mut player_query: Query<(
-        &TnuaController<ControlScheme>,
-        &mut TnuaAnimatingState<AnimationState>,
-    )>,
-    mut animation_player_query: Query<&mut AnimationPlayer>,
-    animation_nodes: Option<Res<Animations>>,
+/// Present on the player while it plays an emote. Input is ignored, except for getting up.
+#[derive(Component)]
+struct Emoting {
+    emote: Emote,
+    phase: EmotePhase,
+    /// Where to put the player back once it gets up, when the emote moved it onto a seat.
+    return_to: Option<Vec3>,
+}
+
+impl Emoting {
+    fn new(emote: Emote) -> Self {
+        Self {
+            emote,
+            phase: if emote.clips().0.is_some() {
+                EmotePhase::Entering
+            } else {
+                EmotePhase::Holding
+            },
+            return_to: None,
+        }
+    }
+}
+
+/// Sits down on a nearby seat with the interact key, and steps the emote the player is in
+/// through its phases.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn handle_emotes(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &TnuaController<ControlScheme>,
+            &Hands,
+            Option<&mut Emoting>,
+        ),
+        (With<Player>, Without<Knockdown>, Without<Staggered>),
+    >,
+    seats: Query<&Transform, (With<Seat>, Without<Player>)>,
+    animation_player: Query<&AnimationPlayer>,
+    animations: Option<Res<Animations>>,
+    settings: Res<Settings>,
 ) {
-    let Ok((controller, mut animating_state)) = player_query.single_mut() else {
-        return;
-    };
-    let Ok(mut animation_player) = animation_player_query.single_mut() else {
+    let Ok((player, mut transform, controller, hands, emoting)) = player_query.single_mut() else {
         return;
     };
-    let Some(animation_nodes) = animation_nodes else {
+    let bindings = &settings.key_bindings;
+    let interact = keyboard.just_pressed(bindings.interact);
+
+    let Some(mut emoting) = emoting else {
+        if !interact
+            || hands.held.is_some()
+            || controller.basis_memory.standing_on_entity().is_none()
+        {
+            return;
+        }
+        let nearest = seats
+            .iter()
+            .map(|seat| (seat, seat.translation.distance(transform.translation)))
+            .filter(|(_, distance)| *distance < SEAT_REACH)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        let Some((seat, _)) = nearest else {
+            return;
+        };
+        // Stand at the front edge; sitting down moves the hips back onto the seat.
+        let return_to = transform.translation;
+        transform.translation = seat.translation + *seat.forward() * SEAT_DEPTH / 2.0;
+        transform.rotation = seat.rotation;
+        commands.entity(player).insert((
+            Emoting {
+                return_to: Some(return_to),
+                ..Emoting::new(Emote::SitChair)
+            },
+            RigidBodyDisabled,
+            ColliderDisabled,
+        ));
         return;
     };
-
-    let current_status_for_animating = match controller.current_action.as_ref() {
-        Some(ControlSchemeActionState::Jump(state)) => match state.memory {
-            TnuaBuiltinJumpMemory::NoJump => return,
-            TnuaBuiltinJumpMemory::StartingJump { .. } => AnimationState::Jumping,
-            TnuaBuiltinJumpMemory::SlowDownTooFastSlopeJump { .. } => AnimationState::Jumping,
-            TnuaBuiltinJumpMemory::MaintainingJump { .. } => AnimationState::Jumping,
-            TnuaBuiltinJumpMemory::StoppedMaintainingJump => AnimationState::Jumping,
-            TnuaBuiltinJumpMemory::FallSection => AnimationState::Falling,
-        },
-        Some(ControlSchemeActionState::Dash(_)) => AnimationState::Dashing,
-        None => {
-            if controller.basis_memory.standing_on_entity().is_none() {
-                AnimationState::Falling
-            } else {
-                let speed = controller.basis_memory.running_velocity.length();
-                if 0.01 < speed {
-                    AnimationState::Running(0.1 * speed)
-                } else {
-                    AnimationState::Standing
-                }
+
+    let wants_up = interact
+        || keyboard.any_just_pressed([
+            bindings.forward,
+            bindings.left,
+            bindings.back,
+            bindings.right,
+            bindings.jump,
+        ]);
+    let finished = |clip: Option<&str>| match (&animations, animation_player.single()) {
+        (Some(animations), Ok(animation_player)) => {
+            clip.is_none_or(|clip| animations.is_finished(animation_player, clip))
+        }
+        _ => true,
+    };
+
+    let (enter, hold, exit) = emoting.emote.clips();
+    let done = match emoting.phase {
+        EmotePhase::Entering => {
+            if finished(enter) {
+                emoting.phase = EmotePhase::Holding;
             }
+            false
         }
+        // One-shot emotes end when their clip does.
+        EmotePhase::Holding if enter.is_none() => finished(Some(hold)) || wants_up,
+        EmotePhase::Holding => {
+            if wants_up {
+                emoting.phase = EmotePhase::Exiting;
+            }
+            false
+        }
+        EmotePhase::Exiting => finished(exit),
     };
+    if done {
+        if let Some(return_to) = emoting.return_to {
+            transform.translation = return_to;
+        }
+        commands
+            .entity(player)
+            .remove::<(Emoting, RigidBodyDisabled, ColliderDisabled)>();
+    }
+}
 
-    let animating_directive = animating_state.update_by_discriminant(current_status_for_animating);
+/// Open while the emote wheel key is held. Pushing the mouse towards an emote picks it, letting go
+/// of the key plays it.
+#[derive(Resource, Default)]
+struct EmoteWheel {
+    pointer: Vec2,
+}
 
-    match animating_directive {
-        TnuaAnimatingStateDirective::Maintain { state } => {
-            if let AnimationState::Running(speed) = state
-                && let Some(animation) =
-                    animation_player.animation_mut(animation_nodes.animations["Running_A"])
-            {
-                animation.set_speed(*speed);
-            }
-            if let AnimationState::Standing = state {
-                let landing_finished = if let Some(landing_anim) =
-                    animation_player.animation(animation_nodes.animations["Jump_Land"])
-                {
-                    landing_anim.is_finished()
-                } else {
-                    false
-                };
+#[derive(Component)]
+struct EmoteWheelUi;
 
-                if landing_finished {
-                    animation_player
-                        .start(animation_nodes.animations["Idle"])
-                        .set_speed(1.0)
-                        .repeat();
-                }
-            }
+#[derive(Component)]
+struct EmoteWheelOption(usize);
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn handle_emote_wheel(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut mouse_motion: MessageReader<MouseMotion>,
+    wheel: Option<ResMut<EmoteWheel>>,
+    wheel_ui: Query<Entity, With<EmoteWheelUi>>,
+    mut options: Query<(&EmoteWheelOption, &mut BackgroundColor)>,
+    player_query: Query<
+        (Entity, &TnuaController<ControlScheme>),
+        (
+            With<Player>,
+            Without<Knockdown>,
+            Without<Staggered>,
+            Without<Emoting>,
+        ),
+    >,
+) {
+    let Some(mut wheel) = wheel else {
+        if keyboard.just_pressed(settings.key_bindings.emote_wheel) {
+            commands.init_resource::<EmoteWheel>();
+            spawn_emote_wheel(&mut commands);
         }
-        TnuaAnimatingStateDirective::Alter { old_state, state } => {
-            animation_player.stop_all();
-
-            match state {
-                AnimationState::Standing => {
-                    if let Some(AnimationState::Falling) = old_state {
-                        animation_player
-                            .start(animation_nodes.animations["Jump_Land"])
-                            .set_speed(1.0);
-                    } else {
-                        animation_player
-                            .start(animation_nodes.animations["Idle"])
-                            .set_speed(1.0)
-                            .repeat();
-                    }
-                }
-                AnimationState::Running(speed) => {
-                    animation_player
-                        .start(animation_nodes.animations["Running_A"])
-                        .set_speed(*speed)
-                        .repeat();
-                }
-                AnimationState::Jumping => {
-                    animation_player
-                        .start(animation_nodes.animations["Jump_Start"])
-                        .set_speed(2.0);
-                }
-                AnimationState::Falling => {
-                    animation_player
-                        .start(animation_nodes.animations["Jump_Idle"])
-                        .set_speed(1.0);
-                }
-                AnimationState::Dashing => {
-                    animation_player
-                        .start(animation_nodes.animations["Dodge_Forward"])
-                        .set_speed(1.0);
+        return;
+    };
+
+    for motion in mouse_motion.read() {
+        wheel.pointer = (wheel.pointer + motion.delta).clamp_length_max(EMOTE_WHEEL_RADIUS);
+    }
+    let selected = (EMOTE_WHEEL_DEAD_ZONE < wheel.pointer.length()).then(|| {
+        // Screen space: y points down, the first option sits at the top.
+        let sector = TAU / WHEEL_EMOTES.len() as f32;
+        let angle = wheel.pointer.y.atan2(wheel.pointer.x) + FRAC_PI_2;
+        (angle / sector)
+            .round()
+            .rem_euclid(WHEEL_EMOTES.len() as f32) as usize
+    });
+    for (option, mut background) in &mut options {
+        background.0 = if Some(option.0) == selected {
+            Color::srgba(1.0, 1.0, 1.0, 0.6)
+        } else {
+            Color::srgba(0.0, 0.0, 0.0, 0.5)
+        };
+    }
+
+    if !keyboard.just_released(settings.key_bindings.emote_wheel) {
+        return;
+    }
+    for entity in &wheel_ui {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<EmoteWheel>();
+    if let (Some(selected), Ok((player, controller))) = (selected, player_query.single())
+        && controller.basis_memory.standing_on_entity().is_some()
+    {
+        commands
+            .entity(player)
+            .insert(Emoting::new(WHEEL_EMOTES[selected]));
+    }
+}
+
+fn spawn_emote_wheel(commands: &mut Commands) {
+    commands
+        .spawn((
+            EmoteWheelUi,
+            DespawnOnExit(InCourse),
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(Node::default()).with_children(|hub| {
+                for (i, emote) in WHEEL_EMOTES.iter().enumerate() {
+                    let angle = i as f32 * TAU / WHEEL_EMOTES.len() as f32 - FRAC_PI_2;
+                    let position = EMOTE_WHEEL_RADIUS * Vec2::from_angle(angle);
+                    hub.spawn((
+                        EmoteWheelOption(i),
+                        Node {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(position.x - 60.0),
+                            top: Val::Px(position.y - 15.0),
+                            width: Val::Px(120.0),
+                            height: Val::Px(30.0),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+                    ))
+                    .with_child(Text::new(emote.name()));
                 }
-                _ => {}
-            }
-        }
+            });
+        });
+}
+
+#[allow(clippy::type_complexity)]
+fn cheer_on_course_complete(
+    mut commands: Commands,
+    result: Option<Res<CourseResult>>,
+    player_query: Query<
+        Entity,
+        (
+            With<Player>,
+            Without<Knockdown>,
+            Without<Staggered>,
+            Without<Emoting>,
+        ),
+    >,
+) {
+    if !result.is_some_and(|result| result.is_added() && result.success()) {
+        return;
+    }
+    if let Ok(player) = player_query.single() {
+        commands.entity(player).insert(Emoting::new(Emote::Cheer));
     }
 }
 
@@ -636,6 +5062,7 @@ struct ThirdPersonCamera {
     pitch: f32,
     yaw: f32,
     sensitivity: f32,
+    invert_y: bool,
 }
 
 impl Default for ThirdPersonCamera {
@@ -645,6 +5072,7 @@ impl Default for ThirdPersonCamera {
             pitch: -0.5,
             yaw: 0.0,
             sensitivity: 0.003,
+            invert_y: false,
         }
     }
 }
@@ -654,6 +5082,8 @@ fn orbit_camera(
     player_query: Query<&Transform, (With<Player>, Without<ThirdPersonCamera>)>,
     mut mouse_motion: MessageReader<MouseMotion>,
     mut mouse_wheel: MessageReader<MouseWheel>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    build: Option<Res<BuildPhase>>,
 ) {
     let Ok((mut camera, mut camera_transform)) = camera_query.single_mut() else {
         return;
@@ -662,10 +5092,14 @@ fn orbit_camera(
         return;
     };
 
+    // While building, the cursor is needed for placing, so only orbit with the right button held.
     let mut rotation_delta = Vec2::ZERO;
     for event in mouse_motion.read() {
         rotation_delta += event.delta;
     }
+    if build.is_some() && !mouse.pressed(MouseButton::Right) {
+        rotation_delta = Vec2::ZERO;
+    }
 
     let mut zoom_delta = 0.0;
     for event in mouse_wheel.read() {
@@ -673,7 +5107,12 @@ fn orbit_camera(
     }
 
     camera.yaw -= rotation_delta.x * camera.sensitivity;
-    camera.pitch -= rotation_delta.y * camera.sensitivity;
+    let pitch_delta = rotation_delta.y * camera.sensitivity;
+    camera.pitch += if camera.invert_y {
+        pitch_delta
+    } else {
+        -pitch_delta
+    };
     camera.distance += zoom_delta * 0.5;
     camera.distance = camera.distance.clamp(2.0, 20.0);
 
@@ -682,8 +5121,681 @@ fn orbit_camera(
     let rot = Quat::from_euler(EulerRot::YXZ, camera.yaw, camera.pitch, 0.0);
     let offset = rot * Vec3::new(0.0, 0.0, camera.distance);
 
-    let target = player_transform.translation + Vec3::new(0.0, 1.5, 0.0);
+    let target =
+        build.map_or(player_transform.translation, |build| build.focus) + Vec3::new(0.0, 1.5, 0.0);
 
     camera_transform.translation = target + offset;
     camera_transform.look_at(target, Vec3::Y);
 }
+
+/// Maximum recording length in fixed ticks (one minute at the default 64 Hz).
+const MAX_RECORDED_TICKS: usize = 64 * 60;
+
+/// Velocity above which a domino counts as disturbed and the recording starts.
+const CHAIN_START_VELOCITY: f32 = 0.1;
+
+/// Transforms of every `Domino`, captured once per fixed tick from the moment the chain starts.
+#[derive(Resource, Default)]
+struct ChainRecording {
+    frames: Vec<Vec<(Entity, Transform)>>,
+    finished: bool,
+}
+
+fn record_chain(
+    mut recording: ResMut<ChainRecording>,
+    dominoes: Query<(Entity, &Transform, &LinearVelocity, Has<ThrownBall>), With<Domino>>,
+    replay: Option<Res<InstantReplay>>,
+) {
+    if replay.is_some() || recording.finished {
+        return;
+    }
+    // Test throws are recorded along with the chain, but don't start it.
+    if recording.frames.is_empty()
+        && !dominoes
+            .iter()
+            .any(|(_, _, velocity, thrown)| !thrown && velocity.length() > CHAIN_START_VELOCITY)
+    {
+        return;
+    }
+    // Past the cap the rest of the chain goes unrecorded; the replay keeps its start.
+    if recording.frames.len() >= MAX_RECORDED_TICKS {
+        return;
+    }
+    recording.frames.push(
+        dominoes
+            .iter()
+            .map(|(entity, transform, _, _)| (entity, *transform))
+            .collect(),
+    );
+}
+
+#[derive(Clone, Copy)]
+enum ReplayShot {
+    /// High above and to the side, looking down on the whole wave front.
+    Overview,
+    /// Low and close behind the falling dominoes.
+    Tracking,
+    /// Ground level, ahead of the wave front.
+    Ground,
+}
+
+const REPLAY_SHOTS: [ReplayShot; 3] = [
+    ReplayShot::Overview,
+    ReplayShot::Tracking,
+    ReplayShot::Ground,
+];
+
+impl ReplayShot {
+    fn eye(&self, focus: Vec3) -> Vec3 {
+        match self {
+            ReplayShot::Overview => focus + Vec3::new(-12.0, 14.0, -6.0),
+            ReplayShot::Tracking => focus + Vec3::new(-4.0, 1.5, -5.0),
+            ReplayShot::Ground => focus + Vec3::new(2.5, 0.3, 4.0),
+        }
+    }
+}
+
+/// Present while a replay is playing. Physics is paused for the duration.
+#[derive(Resource)]
+struct InstantReplay {
+    frame: f32,
+    speed: f32,
+    shot: usize,
+    focus: Vec3,
+}
+
+fn start_replay(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut recording: ResMut<ChainRecording>,
+    final_domino: Query<(&Transform, &Upright), With<FinalDomino>>,
+    replay: Option<Res<InstantReplay>>,
+    mut physics_time: ResMut<Time<Physics>>,
+) {
+    if replay.is_some() || recording.frames.is_empty() {
+        return;
+    }
+
+    let final_fallen = final_domino
+        .single()
+        .is_ok_and(|(transform, upright)| upright.toppled(transform));
+
+    if !recording.finished && final_fallen {
+        recording.finished = true;
+    } else if !(recording.finished && keyboard.just_pressed(settings.key_bindings.replay)) {
+        return;
+    }
+
+    physics_time.pause();
+    commands.insert_resource(InstantReplay {
+        frame: 0.0,
+        speed: 1.0,
+        shot: 0,
+        focus: recording.frames[0]
+            .iter()
+            .map(|(_, t)| t.translation)
+            .sum::<Vec3>()
+            / recording.frames[0].len().max(1) as f32,
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn play_replay(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    recording: Res<ChainRecording>,
+    replay: Option<ResMut<InstantReplay>>,
+    mut physics_time: ResMut<Time<Physics>>,
+    mut transforms: Query<&mut Transform, (With<Domino>, Without<Camera3d>)>,
+    mut camera: Single<&mut Transform, With<Camera3d>>,
+) {
+    let Some(mut replay) = replay else {
+        return;
+    };
+    let Some(last_frame) = recording.frames.last() else {
+        return;
+    };
+
+    if keyboard.just_pressed(KeyCode::BracketLeft) {
+        replay.speed = (replay.speed * 0.5).max(0.125);
+    }
+    if keyboard.just_pressed(KeyCode::BracketRight) {
+        replay.speed = (replay.speed * 2.0).min(4.0);
+    }
+
+    replay.frame += time.delta_secs() / fixed_time.timestep().as_secs_f32() * replay.speed;
+    if replay.frame >= recording.frames.len() as f32 {
+        replay.frame = 0.0;
+        replay.shot += 1;
+    }
+
+    if replay.shot >= REPLAY_SHOTS.len() || keyboard.just_pressed(KeyCode::Enter) {
+        // Leave every piece exactly where the simulation left it.
+        for (entity, recorded) in last_frame {
+            if let Ok(mut transform) = transforms.get_mut(*entity) {
+                *transform = *recorded;
+            }
+        }
+        physics_time.unpause();
+        commands.remove_resource::<InstantReplay>();
+        return;
+    }
+
+    let index = replay.frame as usize;
+    let frame = &recording.frames[index];
+    // Pieces can be spawned mid-chain (e.g. thrown balls), so match frames up by entity.
+    let previous = recording.frames[index.saturating_sub(1)]
+        .iter()
+        .map(|(entity, transform)| (*entity, transform.translation))
+        .collect::<HashMap<_, _>>();
+
+    let mut moving_sum = Vec3::ZERO;
+    let mut moving_count = 0;
+    for (entity, recorded) in frame {
+        if let Ok(mut transform) = transforms.get_mut(*entity) {
+            *transform = *recorded;
+        }
+        if previous
+            .get(entity)
+            .is_some_and(|before| recorded.translation.distance_squared(*before) > 1e-6)
+        {
+            moving_sum += recorded.translation;
+            moving_count += 1;
+        }
+    }
+
+    if moving_count > 0 {
+        let target = moving_sum / moving_count as f32;
+        let t = 1.0 - (-3.0 * time.delta_secs()).exp();
+        replay.focus = replay.focus.lerp(target, t);
+    }
+
+    let shot = REPLAY_SHOTS[replay.shot];
+    camera.translation = shot.eye(replay.focus);
+    camera.look_at(replay.focus, Vec3::Y);
+}
+
+/// The debug panel text. Hidden until F3 is pressed.
+#[derive(Component)]
+struct DebugPanel;
+
+fn setup_debug_panel(mut commands: Commands) {
+    commands.spawn((
+        DebugPanel,
+        Text::default(),
+        TextFont::from_font_size(14.0),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            right: Val::Px(12.0),
+            padding: UiRect::all(Val::Px(8.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        Visibility::Hidden,
+    ));
+}
+
+/// F3 shows the debug panel. F4, F5 and F6 toggle the collider, contact and sleeping-body gizmos.
+fn toggle_debug_views(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut gizmo_store: ResMut<GizmoConfigStore>,
+    mut panel: Single<&mut Visibility, With<DebugPanel>>,
+) {
+    if keyboard.just_pressed(KeyCode::F3) {
+        panel.toggle_visible_hidden();
+    }
+
+    let defaults = PhysicsGizmos::default();
+    let (_, gizmos) = gizmo_store.config_mut::<PhysicsGizmos>();
+    if keyboard.just_pressed(KeyCode::F4) {
+        let shown = gizmos.collider_color.is_none();
+        gizmos.collider_color = defaults.collider_color.filter(|_| shown);
+        gizmos.axis_lengths = defaults.axis_lengths.filter(|_| shown);
+    }
+    if keyboard.just_pressed(KeyCode::F5) {
+        let shown = gizmos.contact_point_color.is_none();
+        gizmos.contact_point_color = Some(css::LIME.into()).filter(|_| shown);
+        gizmos.contact_normal_color = Some(css::AQUA.into()).filter(|_| shown);
+    }
+    if keyboard.just_pressed(KeyCode::F6) {
+        gizmos.sleeping_color_multiplier = match gizmos.sleeping_color_multiplier {
+            Some(_) => None,
+            None => defaults.sleeping_color_multiplier,
+        };
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_debug_panel(
+    mut panel: Single<(&mut Text, &Visibility), With<DebugPanel>>,
+    player_query: Query<&TnuaController<ControlScheme>, With<Player>>,
+    camera: Single<&ThirdPersonCamera>,
+    dominoes: Query<(&Transform, &LinearVelocity, Has<Sleeping>), With<Domino>>,
+    physics_diagnostics: Res<PhysicsTotalDiagnostics>,
+    physics_time: Res<Time<Physics>>,
+    fixed_time: Res<Time<Fixed>>,
+    gizmo_store: Res<GizmoConfigStore>,
+) {
+    let (text, visibility) = &mut *panel;
+    if **visibility == Visibility::Hidden {
+        return;
+    }
+
+    let controller = match player_query.single() {
+        Ok(controller) => {
+            let velocity = controller.basis_memory.running_velocity;
+            format!(
+                "Action: {:?}\nRunning velocity: ({:.2}, {:.2}, {:.2}) = {:.2} m/s\nStanding on: {}",
+                controller.action_discriminant(),
+                velocity.x,
+                velocity.y,
+                velocity.z,
+                velocity.length(),
+                controller
+                    .basis_memory
+                    .standing_on_entity()
+                    .map_or("nothing".to_string(), |entity| entity.to_string()),
+            )
+        }
+        Err(_) => "No player".to_string(),
+    };
+
+    let (mut standing, mut toppling, mut fallen, mut sleeping) = (0, 0, 0, 0);
+    for (transform, velocity, asleep) in &dominoes {
+        if transform.up().dot(Vec3::Y) < 0.5 {
+            fallen += 1;
+        } else if 0.1 < velocity.length() {
+            toppling += 1;
+        } else {
+            standing += 1;
+        }
+        if asleep {
+            sleeping += 1;
+        }
+    }
+
+    let (_, gizmos) = gizmo_store.config::<PhysicsGizmos>();
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    text.0 = format!(
+        "{controller}\n\n\
+         Camera: distance {:.1}, pitch {:.1}°, yaw {:.1}°, sensitivity {}\n\
+         Dominoes: {standing} standing, {toppling} toppling, {fallen} fallen ({sleeping} asleep)\n\
+         Physics: step {}, {:.2} ms (every {:.1} ms){}\n\n\
+         F3 panel, F4 colliders ({}), F5 contacts ({}), F6 sleeping ({})",
+        camera.distance,
+        camera.pitch.to_degrees(),
+        camera.yaw.to_degrees(),
+        camera.sensitivity,
+        physics_diagnostics.step_number,
+        physics_diagnostics.step_time.as_secs_f64() * 1000.0,
+        fixed_time.timestep().as_secs_f64() * 1000.0,
+        if physics_time.is_paused() {
+            ", paused"
+        } else {
+            ""
+        },
+        on_off(gizmos.collider_color.is_some()),
+        on_off(gizmos.contact_point_color.is_some()),
+        on_off(gizmos.sleeping_color_multiplier.is_some()),
+    );
+}
+
+/// Where the settings are kept: a file next to the game on native, `localStorage` on the web.
+const SETTINGS_PATH: &str = "settings.ron";
+
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+struct Settings {
+    mouse_sensitivity: f32,
+    invert_y: bool,
+    /// Vertical field of view, in degrees.
+    fov: f32,
+    shadows: bool,
+    master_volume: f32,
+    effects_volume: f32,
+    key_bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: 0.003,
+            invert_y: false,
+            fov: 45.0,
+            shadows: true,
+            master_volume: 1.0,
+            effects_volume: 1.0,
+            key_bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Falls back to the defaults if nothing was saved yet or it can't be read.
+    fn load() -> Self {
+        let Some(contents) = read_settings() else {
+            return Self::default();
+        };
+        ron::from_str(&contents).unwrap_or_else(|err| {
+            warn!("Ignoring invalid settings: {err}");
+            Self::default()
+        })
+    }
+
+    fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| write_settings(&contents));
+        if let Err(err) = result {
+            warn!("Failed to save settings: {err}");
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_settings() -> Option<String> {
+    std::fs::read_to_string(SETTINGS_PATH).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_settings(contents: &str) -> Result<(), String> {
+    std::fs::write(SETTINGS_PATH, contents).map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_settings() -> Option<String> {
+    local_storage()?.get_item(SETTINGS_PATH).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_settings(contents: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or("localStorage is unavailable")?
+        .set_item(SETTINGS_PATH, contents)
+        .map_err(|err| format!("{err:?}"))
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+struct KeyBindings {
+    forward: KeyCode,
+    back: KeyCode,
+    left: KeyCode,
+    right: KeyCode,
+    jump: KeyCode,
+    dash: KeyCode,
+    crouch: KeyCode,
+    interact: KeyCode,
+    punch: KeyCode,
+    kick: KeyCode,
+    spin: KeyCode,
+    turn_in_place: KeyCode,
+    emote_wheel: KeyCode,
+    rotate_left: KeyCode,
+    rotate_right: KeyCode,
+    replay: KeyCode,
+    puzzle: KeyCode,
+    toggle_knockdowns: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::KeyW,
+            back: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            jump: KeyCode::Space,
+            dash: KeyCode::ShiftLeft,
+            crouch: KeyCode::KeyC,
+            interact: KeyCode::KeyE,
+            punch: KeyCode::KeyF,
+            kick: KeyCode::KeyG,
+            spin: KeyCode::KeyQ,
+            turn_in_place: KeyCode::AltLeft,
+            emote_wheel: KeyCode::KeyT,
+            rotate_left: KeyCode::KeyZ,
+            rotate_right: KeyCode::KeyX,
+            replay: KeyCode::KeyR,
+            puzzle: KeyCode::KeyP,
+            toggle_knockdowns: KeyCode::KeyK,
+        }
+    }
+}
+
+impl KeyBindings {
+    fn entries(&mut self) -> [(&'static str, &mut KeyCode); 18] {
+        [
+            ("Forward", &mut self.forward),
+            ("Back", &mut self.back),
+            ("Left", &mut self.left),
+            ("Right", &mut self.right),
+            ("Jump", &mut self.jump),
+            ("Dash", &mut self.dash),
+            ("Crouch / let go", &mut self.crouch),
+            ("Pick up / set down", &mut self.interact),
+            ("Punch", &mut self.punch),
+            ("Kick", &mut self.kick),
+            ("Spin", &mut self.spin),
+            ("Turn in place", &mut self.turn_in_place),
+            ("Emote wheel", &mut self.emote_wheel),
+            ("Rotate left", &mut self.rotate_left),
+            ("Rotate right", &mut self.rotate_right),
+            ("Instant replay", &mut self.replay),
+            ("Puzzle", &mut self.puzzle),
+            ("Knockdowns on/off", &mut self.toggle_knockdowns),
+        ]
+    }
+}
+
+/// Keys with a fixed meaning in menus, the build phase, the replay or the debug views, which can't
+/// be bound to an action.
+const RESERVED_KEYS: [KeyCode; 21] = [
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::KeyM,
+];
+
+/// Number of rows in the settings menu before the key bindings.
+const SETTINGS_ROWS: usize = 6;
+
+/// Present while the settings menu is open, over the main menu or the pause menu.
+#[derive(Resource, Default)]
+struct SettingsMenu {
+    selected: usize,
+    /// Waiting for a key to bind to the selected action.
+    rebinding: bool,
+    /// The last key refused while rebinding.
+    reserved: Option<KeyCode>,
+}
+
+#[derive(Component)]
+struct SettingsMenuText;
+
+fn open_settings(mut commands: Commands, keyboard: Res<ButtonInput<KeyCode>>) {
+    if !keyboard.just_pressed(KeyCode::KeyS) {
+        return;
+    }
+    commands.insert_resource(SettingsMenu::default());
+    commands.spawn((
+        SettingsMenuText,
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            right: Val::Px(12.0),
+            padding: UiRect::all(Val::Px(12.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.8)),
+        // Above the pause menu's backdrop.
+        GlobalZIndex(1),
+    ));
+}
+
+/// Up/Down pick a setting, Left/Right change it, Enter toggles it or rebinds the key and Esc
+/// saves and closes the menu.
+fn handle_settings_menu(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut menu: ResMut<SettingsMenu>,
+    mut settings: ResMut<Settings>,
+    menu_text: Query<Entity, With<SettingsMenuText>>,
+) {
+    if menu.rebinding {
+        if keyboard.just_pressed(KeyCode::Escape) {
+            menu.rebinding = false;
+            menu.reserved = None;
+        } else if let Some(&key) = keyboard.get_just_pressed().next() {
+            if RESERVED_KEYS.contains(&key) {
+                menu.reserved = Some(key);
+                return;
+            }
+            // A key already bound to another action is swapped, so no two actions share one.
+            let mut entries = settings.key_bindings.entries();
+            let previous = *entries[menu.selected - SETTINGS_ROWS].1;
+            for (_, bound) in entries.iter_mut() {
+                if **bound == key {
+                    **bound = previous;
+                }
+            }
+            *entries[menu.selected - SETTINGS_ROWS].1 = key;
+            menu.rebinding = false;
+            menu.reserved = None;
+        }
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Escape) {
+        settings.save();
+        commands.remove_resource::<SettingsMenu>();
+        for entity in &menu_text {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+
+    let rows = SETTINGS_ROWS + settings.key_bindings.entries().len();
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        menu.selected = (menu.selected + rows - 1) % rows;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        menu.selected = (menu.selected + 1) % rows;
+    }
+
+    let mut step = 0.0;
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        step -= 1.0;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        step += 1.0;
+    }
+    let toggle = keyboard.just_pressed(KeyCode::Enter);
+    if step == 0.0 && !toggle {
+        return;
+    }
+
+    match menu.selected {
+        0 => {
+            settings.mouse_sensitivity =
+                (settings.mouse_sensitivity + step * 0.0005).clamp(0.0005, 0.01);
+        }
+        1 => settings.invert_y ^= true,
+        2 => settings.fov = (settings.fov + step * 5.0).clamp(30.0, 110.0),
+        3 => settings.shadows ^= true,
+        4 => settings.master_volume = (settings.master_volume + step * 0.1).clamp(0.0, 1.0),
+        5 => settings.effects_volume = (settings.effects_volume + step * 0.1).clamp(0.0, 1.0),
+        _ => menu.rebinding = toggle,
+    }
+}
+
+fn update_settings_menu(
+    menu: Res<SettingsMenu>,
+    settings: Res<Settings>,
+    mut text: Single<&mut Text, With<SettingsMenuText>>,
+) {
+    if !menu.is_changed() && !settings.is_changed() {
+        return;
+    }
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    let mut rows = vec![
+        format!("Mouse sensitivity: {:.4}", settings.mouse_sensitivity),
+        format!("Invert Y: {}", on_off(settings.invert_y)),
+        format!("Field of view: {:.0}", settings.fov),
+        format!("Shadows: {}", on_off(settings.shadows)),
+        format!("Master volume: {:.0}%", settings.master_volume * 100.0),
+        format!("Effects volume: {:.0}%", settings.effects_volume * 100.0),
+    ];
+    let mut bindings = settings.key_bindings.clone();
+    rows.extend(
+        bindings
+            .entries()
+            .into_iter()
+            .map(|(action, key)| format!("{action}: {key:?}")),
+    );
+
+    let rows = rows
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| match (i == menu.selected, menu.rebinding) {
+            (true, true) => match menu.reserved {
+                Some(key) => format!("> {row} - {key:?} is reserved, press another key"),
+                None => format!("> {row} - press a key"),
+            },
+            (true, false) => format!("> {row}"),
+            (false, _) => format!("  {row}"),
+        })
+        .collect::<Vec<_>>();
+    text.0 = format!(
+        "Settings\n\n{}\n\nUp/Down - select   Left/Right - change\nEnter - toggle / rebind   Esc - save and close\n\n\
+         Fixed keys: Esc - pause   1-6 - choose   Enter - confirm / start puzzle / skip replay\n\
+         Tab - switch piece   Backspace - remove piece   [ ] - replay camera   F3-F6 - debug\n\
+         S - settings   M - main menu",
+        rows.join("\n")
+    );
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut camera: Single<(&mut ThirdPersonCamera, &mut Projection)>,
+    mut lights: Query<&mut DirectionalLight>,
+    mut global_volume: ResMut<GlobalVolume>,
+) {
+    let (third_person, projection) = &mut *camera;
+    third_person.sensitivity = settings.mouse_sensitivity;
+    third_person.invert_y = settings.invert_y;
+    if let Projection::Perspective(perspective) = &mut **projection {
+        perspective.fov = settings.fov.to_radians();
+    }
+    for mut light in &mut lights {
+        light.shadows_enabled = settings.shadows;
+    }
+    global_volume.volume = Volume::Linear(settings.master_volume);
+}